@@ -0,0 +1,22 @@
+extern crate protoc_grpcio;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+
+fn main() {
+    let proto_root = "proto";
+    let out_dir = env::var("OUT_DIR").expect("OUT_DIR is set by cargo.");
+    println!("cargo:rerun-if-changed={}", proto_root);
+    protoc_grpcio::compile_grpc_protos(&["user.proto"], &[proto_root], &out_dir, None)
+        .expect("Unable to compile the gRPC definitions.");
+
+    // The generated files are `include!`d into modules of src/api/grpc/proto,
+    // where their crate level `#![...]` attributes aren't allowed.
+    for file in &["user.rs", "user_grpc.rs"] {
+        let path = Path::new(&out_dir).join(file);
+        let generated = fs::read_to_string(&path).expect("Unable to read the generated gRPC code.");
+        let stripped: Vec<&str> = generated.lines().filter(|line| !line.trim_start().starts_with("#![")).collect();
+        fs::write(&path, stripped.join("\n")).expect("Unable to write the generated gRPC code.");
+    }
+}
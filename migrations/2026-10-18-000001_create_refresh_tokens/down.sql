@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP EVENT IF EXISTS `refresh_tokens_cleaner_event`;
+DROP TABLE `refresh_tokens`;
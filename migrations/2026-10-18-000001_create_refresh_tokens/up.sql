@@ -0,0 +1,22 @@
+-- Only the SHA-256 hash of a refresh token is stored. Every rotation of a
+-- token stays in its `family`, so presenting an already used token revokes
+-- the whole family.
+CREATE TABLE IF NOT EXISTS `refresh_tokens` (
+  `id` INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+  `user_id` INT NOT NULL,
+  `family` VARCHAR(32) NOT NULL,
+  `token_hash` CHAR(64) NOT NULL UNIQUE,
+  `used` TINYINT(1) NOT NULL DEFAULT 0,
+  `revoked` TINYINT(1) NOT NULL DEFAULT 0,
+  `expires_at` DATETIME NOT NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  INDEX (`family`),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8 AUTO_INCREMENT=1;
+
+CREATE EVENT IF NOT EXISTS `refresh_tokens_cleaner_event`
+ON SCHEDULE
+  EVERY 1 DAY
+  COMMENT 'Clean up expired refresh tokens daily'
+  DO
+    DELETE FROM `refresh_tokens` WHERE `expires_at` < NOW();
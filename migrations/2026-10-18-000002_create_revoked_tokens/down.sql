@@ -0,0 +1,3 @@
+DROP EVENT IF EXISTS `revoked_tokens_cleaner_event`;
+ALTER TABLE `users` DROP COLUMN `tokens_revoked_before`;
+DROP TABLE IF EXISTS `revoked_tokens`;
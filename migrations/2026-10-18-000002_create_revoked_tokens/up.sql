@@ -0,0 +1,19 @@
+-- Access tokens revoked before their `exp` by `app.sign_out`, kept until they
+-- would have expired anyway.
+CREATE TABLE IF NOT EXISTS `revoked_tokens` (
+  `jti` VARCHAR(32) PRIMARY KEY NOT NULL,
+  `user_id` INT NOT NULL,
+  `expires_at` DATETIME NOT NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8;
+
+-- Set by `app.sign_out_everywhere`, tokens issued up to this unix timestamp are revoked.
+ALTER TABLE `users` ADD COLUMN `tokens_revoked_before` BIGINT NULL;
+
+CREATE EVENT IF NOT EXISTS `revoked_tokens_cleaner_event`
+ON SCHEDULE
+  EVERY 1 HOUR
+  COMMENT 'Clean up revoked tokens that have expired hourly'
+  DO
+    DELETE FROM `revoked_tokens` WHERE `expires_at` < NOW();
@@ -0,0 +1,3 @@
+-- Set once the user proves they own the email, i.e. sets a password with a
+-- token that was emailed to them. Reported as `email_verified` in ID tokens.
+ALTER TABLE `users` ADD COLUMN `email_verified` TINYINT(1) NOT NULL DEFAULT 0;
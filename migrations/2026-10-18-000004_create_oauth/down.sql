@@ -0,0 +1,3 @@
+DROP EVENT IF EXISTS `oauth_authorization_codes_cleaner_event`;
+DROP TABLE IF EXISTS `oauth_authorization_codes`;
+DROP TABLE IF EXISTS `oauth_clients`;
@@ -0,0 +1,34 @@
+-- OAuth 2.0 clients, registered by hand:
+-- INSERT INTO oauth_clients (client_id, name, redirect_uris) VALUES ('web', 'Web', 'https://example.com/callback');
+-- `redirect_uris` is a space separated list, `/authorize` only redirects to an exact match.
+CREATE TABLE IF NOT EXISTS `oauth_clients` (
+  `id` INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+  `client_id` VARCHAR(64) NOT NULL UNIQUE,
+  `name` VARCHAR(64) NOT NULL,
+  `redirect_uris` TEXT NOT NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+) ENGINE=InnoDB DEFAULT CHARSET=utf8 AUTO_INCREMENT=1;
+
+-- Only the SHA-256 hash of a code is stored, codes are deleted once exchanged.
+CREATE TABLE IF NOT EXISTS `oauth_authorization_codes` (
+  `id` INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+  `code_hash` CHAR(64) NOT NULL UNIQUE,
+  `client_id` INT NOT NULL,
+  `user_id` INT NOT NULL,
+  `redirect_uri` VARCHAR(255) NOT NULL,
+  `code_challenge` VARCHAR(128) NOT NULL,
+  `scope` VARCHAR(255) NOT NULL,
+  `nonce` VARCHAR(255) NULL,
+  `auth_time` BIGINT NOT NULL,
+  `expires_at` DATETIME NOT NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (client_id) REFERENCES oauth_clients(id) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8 AUTO_INCREMENT=1;
+
+CREATE EVENT IF NOT EXISTS `oauth_authorization_codes_cleaner_event`
+ON SCHEDULE
+  EVERY 1 HOUR
+  COMMENT 'Clean up expired authorization codes hourly'
+  DO
+    DELETE FROM `oauth_authorization_codes` WHERE `expires_at` < NOW();
@@ -0,0 +1,3 @@
+ALTER TABLE `oauth_clients`
+  DROP COLUMN `client_secret_hash`,
+  DROP COLUMN `scopes`;
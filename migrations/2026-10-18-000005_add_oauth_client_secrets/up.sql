@@ -0,0 +1,7 @@
+-- Confidential clients, e.g. internal workers using the client_credentials grant:
+-- INSERT INTO oauth_clients (client_id, name, redirect_uris, client_secret_hash, scopes)
+-- VALUES ('mailer', 'Mailer', '', SHA2('<secret>', 256), 'mail:send users:read');
+-- `scopes` is the space separated list of scopes the client may request.
+ALTER TABLE `oauth_clients`
+  ADD COLUMN `client_secret_hash` CHAR(64) NULL,
+  ADD COLUMN `scopes` VARCHAR(255) NOT NULL DEFAULT '';
@@ -0,0 +1,26 @@
+-- RFC 8628 device authorization requests. `approved` is NULL until the user
+-- approves (1) or denies (0) the `user_code`, the device polls with its
+-- `device_code`, of which only the SHA-256 hash is stored.
+CREATE TABLE IF NOT EXISTS `oauth_device_codes` (
+  `id` INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+  `device_code_hash` CHAR(64) NOT NULL UNIQUE,
+  `user_code` CHAR(8) NOT NULL UNIQUE,
+  `client_id` INT NOT NULL,
+  `scope` VARCHAR(255) NOT NULL,
+  `user_id` INT NULL,
+  `approved` TINYINT(1) NULL,
+  `approved_at` BIGINT NULL,
+  `interval` INT NOT NULL,
+  `last_polled_at` BIGINT NULL,
+  `expires_at` DATETIME NOT NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (client_id) REFERENCES oauth_clients(id) ON UPDATE CASCADE ON DELETE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8 AUTO_INCREMENT=1;
+
+CREATE EVENT IF NOT EXISTS `oauth_device_codes_cleaner_event`
+ON SCHEDULE
+  EVERY 1 HOUR
+  COMMENT 'Clean up expired device codes hourly'
+  DO
+    DELETE FROM `oauth_device_codes` WHERE `expires_at` < NOW();
@@ -0,0 +1,2 @@
+ALTER TABLE `refresh_tokens` DROP COLUMN `audience`;
+ALTER TABLE `users` DROP COLUMN `subject`;
@@ -0,0 +1,10 @@
+-- Opaque, stable identifier of a user, the `sub` of their tokens. Unlike `id`
+-- it doesn't leak how many users there are or when someone signed up. With
+-- SUBJECT_TYPE=pairwise every client sees a hash of it instead.
+ALTER TABLE `users` ADD COLUMN `subject` VARCHAR(64) NULL;
+UPDATE `users` SET `subject` = LEFT(SHA2(CONCAT(UUID(), RAND(), `id`), 256), 32) WHERE `subject` IS NULL;
+ALTER TABLE `users` MODIFY `subject` VARCHAR(64) NOT NULL, ADD UNIQUE (`subject`);
+
+-- Client the refresh token was issued to, or APP_NAME for `app.sign_in`, so
+-- rotated access tokens keep the same pairwise `sub`. NULL means APP_NAME.
+ALTER TABLE `refresh_tokens` ADD COLUMN `audience` VARCHAR(255) NULL;
@@ -0,0 +1 @@
+ALTER TABLE `users` MODIFY `password` VARCHAR(128) NULL;
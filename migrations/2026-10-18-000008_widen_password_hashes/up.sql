@@ -0,0 +1,3 @@
+-- Argon2id PHC strings carry their parameters and salt, so `salt` is only
+-- set on users whose password is still a bcrypt hash.
+ALTER TABLE `users` MODIFY `password` VARCHAR(255) NULL;
@@ -0,0 +1,11 @@
+-- The last PASSWORD_HISTORY password hashes of every user, including the
+-- current one, so passwords can't be reused. `salt` is only set on bcrypt hashes.
+CREATE TABLE IF NOT EXISTS `password_history` (
+  `id` INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+  `user_id` INT NOT NULL,
+  `password` VARCHAR(255) NOT NULL,
+  `salt` VARCHAR(4) NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  INDEX (`user_id`, `id`),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8 AUTO_INCREMENT=1;
@@ -0,0 +1,2 @@
+DROP EVENT IF EXISTS `oauth_form_tokens_cleaner_event`;
+DROP TABLE IF EXISTS `oauth_form_tokens`;
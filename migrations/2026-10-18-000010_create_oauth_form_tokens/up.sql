@@ -0,0 +1,21 @@
+-- Single use tokens of the `/authorize` sign in and consent forms, bound to
+-- the authorization request they were shown for. `user_id` and `auth_time` are
+-- set once the user signed in, i.e. for the consent form. Only the SHA-256
+-- hash of a token is stored.
+CREATE TABLE IF NOT EXISTS `oauth_form_tokens` (
+  `id` INT PRIMARY KEY NOT NULL AUTO_INCREMENT,
+  `token_hash` CHAR(64) NOT NULL UNIQUE,
+  `request_hash` CHAR(64) NOT NULL,
+  `user_id` INT NULL,
+  `auth_time` BIGINT NULL,
+  `expires_at` DATETIME NOT NULL,
+  `date_created` TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON UPDATE CASCADE ON DELETE CASCADE
+) ENGINE=InnoDB DEFAULT CHARSET=utf8 AUTO_INCREMENT=1;
+
+CREATE EVENT IF NOT EXISTS `oauth_form_tokens_cleaner_event`
+ON SCHEDULE
+  EVERY 1 HOUR
+  COMMENT 'Clean up expired authorization form tokens hourly'
+  DO
+    DELETE FROM `oauth_form_tokens` WHERE `expires_at` < NOW();
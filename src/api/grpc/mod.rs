@@ -0,0 +1,201 @@
+//! gRPC server for `proto/user.proto`, started on `GRPC_PORT` next to the HTTP server.
+//!
+//! `DTOErrors` are mapped to status codes: `ValidationError` is INVALID_ARGUMENT
+//! with `{ "validation": ... }` as JSON in the message, `DatabaseError` is
+//! INTERNAL, and `ApplicationError` depends on the method, e.g. UNAUTHENTICATED
+//! for `SignIn` and NOT_FOUND for `IdentityCheck`.
+pub mod proto;
+
+use crate::domain::user::{self, DTOErrors};
+use actix_web::web;
+use futures::Future;
+use grpcio::{Environment, RpcContext, RpcStatus, RpcStatusCode, Server, ServerBuilder, UnarySink};
+use self::proto::user::{
+    AuthenticateReply, AuthenticateRequest, Empty, ForgotMyPasswordRequest, IdentityCheckRequest, RefreshTokenRequest, SignInReply, SignInRequest,
+    SignUpRequest, SignUpWithoutPasswordRequest, UpdatePasswordRequest
+};
+use self::proto::user_grpc::{self, UserService};
+use std::sync::Arc;
+
+fn status(e: DTOErrors, application: RpcStatusCode) -> RpcStatus {
+    match e {
+        DTOErrors::ValidationError(errors) => RpcStatus::new(
+            RpcStatusCode::InvalidArgument,
+            Some(json!({ "validation": errors }).to_string())
+        ),
+        DTOErrors::ApplicationError(reason) => RpcStatus::new(application, Some(reason)),
+        DTOErrors::DatabaseError(reason) => RpcStatus::new(RpcStatusCode::Internal, Some(reason))
+    }
+}
+
+fn signed_in(tokens: user::Tokens) -> SignInReply {
+    let mut signed_in = SignInReply::new();
+    signed_in.set_token(tokens.access_token);
+    signed_in.set_refresh_token(tokens.refresh_token.unwrap_or_default());
+    signed_in.set_expires_in(tokens.expires_in);
+    signed_in.set_id_token(tokens.id_token.unwrap_or_default());
+    signed_in
+}
+
+fn authenticated(identity: user::Identity) -> AuthenticateReply {
+    let mut reply = AuthenticateReply::new();
+    reply.set_sub(identity.sub);
+    match identity.principal {
+        user::Principal::User { username, email } => {
+            reply.set_username(username);
+            reply.set_email(email);
+        },
+        user::Principal::Client { client_id, .. } => reply.set_client_id(client_id)
+    };
+    reply.set_id(identity.id.unwrap_or_default() as u64);
+    reply.set_scopes(identity.scopes.into());
+    reply.set_exp(identity.exp);
+    reply.set_expires_in(identity.expires_in);
+    reply
+}
+
+fn reply<T: Send + 'static>(ctx: RpcContext, sink: UnarySink<T>, result: Result<T, RpcStatus>) {
+    let f = match result {
+        Ok(reply) => sink.success(reply),
+        Err(status) => sink.fail(status)
+    };
+    ctx.spawn(f.map_err(|e| log::error!("Unable to send gRPC reply: {:?}", e)));
+}
+
+#[derive(Clone)]
+struct UserServiceImpl {
+    data: web::Data<crate::AppState>
+}
+
+impl UserService for UserServiceImpl {
+    fn sign_up(&mut self, ctx: RpcContext, req: SignUpRequest, sink: UnarySink<Empty>) {
+        let api_param = user::SignUpDTO {
+            username: req.get_username().to_string(),
+            email: req.get_email().to_string(),
+            password: req.get_password().to_string()
+        };
+        let result = user::sign_up::run(&self.data.config, &self.data.db_conn, &api_param)
+            .map(|_| Empty::new())
+            .map_err(|e| status(e, RpcStatusCode::FailedPrecondition));
+        reply(ctx, sink, result);
+    }
+
+    fn sign_up_without_password(&mut self, ctx: RpcContext, req: SignUpWithoutPasswordRequest, sink: UnarySink<Empty>) {
+        let api_param = user::SignUpWithoutPasswordDTO {
+            username: req.get_username().to_string(),
+            email: req.get_email().to_string()
+        };
+        let result = user::sign_up_without_password::run(&self.data.config, &self.data.db_conn, &api_param)
+            .map(|_| Empty::new())
+            .map_err(|e| status(e, RpcStatusCode::FailedPrecondition));
+        reply(ctx, sink, result);
+    }
+
+    fn sign_in(&mut self, ctx: RpcContext, req: SignInRequest, sink: UnarySink<SignInReply>) {
+        let api_param = user::SignInDTO {
+            username_or_email: req.get_username_or_email().to_string(),
+            password: req.get_password().to_string(),
+            nonce: Some(req.get_nonce().to_string()).filter(|nonce| !nonce.is_empty())
+        };
+        let result = user::sign_in::run(&self.data.config, &self.data.db_conn, &api_param)
+            .map(signed_in)
+            .map_err(|e| status(e, RpcStatusCode::Unauthenticated));
+        reply(ctx, sink, result);
+    }
+
+    fn refresh_token(&mut self, ctx: RpcContext, req: RefreshTokenRequest, sink: UnarySink<SignInReply>) {
+        let api_param = user::RefreshTokenDTO {
+            refresh_token: req.get_refresh_token().to_string()
+        };
+        let result = user::refresh_token::run(&self.data.config, &self.data.db_conn, &api_param)
+            .map(signed_in)
+            .map_err(|e| status(e, RpcStatusCode::Unauthenticated));
+        reply(ctx, sink, result);
+    }
+
+    fn authenticate(&mut self, ctx: RpcContext, req: AuthenticateRequest, sink: UnarySink<AuthenticateReply>) {
+        let api_param = user::AuthenticateDTO {
+            token: req.get_token().to_string(),
+            verify_user: Some(req.get_verify_user())
+        };
+        let result = user::authenticate::run(&self.data.config, &self.data.db_conn, &self.data.revocations, &api_param)
+            .map(authenticated)
+            .map_err(|e| status(e, RpcStatusCode::Unauthenticated));
+        reply(ctx, sink, result);
+    }
+
+    fn update_password(&mut self, ctx: RpcContext, req: UpdatePasswordRequest, sink: UnarySink<Empty>) {
+        let api_param = user::UpdatePasswordDTO {
+            token: req.get_token().to_string(),
+            password: req.get_password().to_string()
+        };
+        let result = user::update_password::run(&self.data.config, &self.data.db_conn, &api_param)
+            .map(|_| Empty::new())
+            .map_err(|e| status(e, RpcStatusCode::NotFound));
+        reply(ctx, sink, result);
+    }
+
+    fn identity_check(&mut self, ctx: RpcContext, req: IdentityCheckRequest, sink: UnarySink<Empty>) {
+        let api_param = user::IdentityCheckDTO {
+            identity: req.get_identity().to_string()
+        };
+        let result = user::identity_check::run(&self.data.db_conn, &api_param)
+            .map(|_| Empty::new())
+            .map_err(|e| status(e, RpcStatusCode::NotFound));
+        reply(ctx, sink, result);
+    }
+
+    fn forgot_my_password(&mut self, ctx: RpcContext, req: ForgotMyPasswordRequest, sink: UnarySink<Empty>) {
+        let api_param = user::ForgotMyPasswordDTO {
+            username_or_email: req.get_username_or_email().to_string()
+        };
+        let result = user::forgot_my_password::run(&self.data.config, &self.data.db_conn, &api_param)
+            .map(|_| Empty::new())
+            .map_err(|e| status(e, RpcStatusCode::NotFound));
+        reply(ctx, sink, result);
+    }
+}
+
+/// Starts the gRPC server, it stops when the returned `Server` is dropped.
+pub fn serve(data: web::Data<crate::AppState>, port: u16) -> Server {
+    let env = Arc::new(Environment::new(2));
+    let service = user_grpc::create_user_service(UserServiceImpl { data });
+    let mut server = ServerBuilder::new(env)
+        .register_service(service)
+        .bind("127.0.0.1", port)
+        .build()
+        .expect("Unable to build the gRPC server.");
+    server.start();
+    for &(ref host, port) in server.bind_addrs() {
+        log::info!("gRPC server listening on {}:{}", host, port);
+    }
+    server
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation_error() {
+        let e = status(DTOErrors::ValidationError(validator::ValidationErrors::new()), RpcStatusCode::NotFound);
+
+        assert_eq!(e.status, RpcStatusCode::InvalidArgument);
+        assert_eq!(e.details, Some("{\"validation\":{}}".to_string()));
+    }
+
+    #[test]
+    fn application_error() {
+        let e = status(DTOErrors::ApplicationError("Identity not found.".to_string()), RpcStatusCode::NotFound);
+
+        assert_eq!(e.status, RpcStatusCode::NotFound);
+        assert_eq!(e.details, Some("Identity not found.".to_string()));
+    }
+
+    #[test]
+    fn database_error() {
+        let e = status(DTOErrors::DatabaseError("Duplicate entry.".to_string()), RpcStatusCode::NotFound);
+
+        assert_eq!(e.status, RpcStatusCode::Internal);
+    }
+}
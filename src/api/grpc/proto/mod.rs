@@ -0,0 +1,10 @@
+// Generated by build.rs from proto/user.proto into OUT_DIR
+#[allow(clippy::all, unknown_lints, unused)]
+pub mod user {
+    include!(concat!(env!("OUT_DIR"), "/user.rs"));
+}
+
+#[allow(clippy::all, unknown_lints, unused)]
+pub mod user_grpc {
+    include!(concat!(env!("OUT_DIR"), "/user_grpc.rs"));
+}
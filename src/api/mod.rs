@@ -1 +1,11 @@
-pub mod user;
\ No newline at end of file
+pub mod auth;
+pub mod grpc;
+pub mod oauth;
+pub mod oidc;
+pub mod openrpc;
+pub mod rest;
+pub mod router;
+pub mod rpc;
+pub mod user;
+pub mod well_known;
+pub mod ws;
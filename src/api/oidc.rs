@@ -0,0 +1,15 @@
+//! OpenID Connect endpoints, advertised by `/.well-known/openid-configuration`.
+use crate::api::auth::Bearer;
+use crate::api::rpc::RPCError;
+use crate::domain::user::{self, DTOErrors};
+use actix_web::{web, HttpResponse};
+
+/// `GET /userinfo` and `POST /userinfo`, https://openid.net/specs/openid-connect-core-1_0.html#UserInfo
+pub fn userinfo(data: web::Data<crate::AppState>, bearer: Bearer) -> HttpResponse {
+    match user::userinfo::run(&data.db_conn, &bearer.0) {
+        Ok(userinfo) => HttpResponse::Ok().json(userinfo),
+        Err(DTOErrors::ApplicationError(e)) => HttpResponse::Unauthorized()
+            .json(json!({ "error": RPCError::unauthorized().with_data(json!({ "application": e })).to_json() })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({ "error": RPCError::from(e).to_json() }))
+    }
+}
@@ -0,0 +1,168 @@
+//! OpenRPC document served by `rpc.discover`, https://spec.open-rpc.org
+//!
+//! Param schemas are read off the DTOs themselves: field names and JSON types
+//! come from serializing `T::default()`, and constraints come from the errors
+//! `validate()` reports for that default instance. Constraints the default
+//! instance already satisfies (e.g. `length(max = ..)` alone) aren't discovered.
+use crate::api::router::Router;
+use crate::api::rpc::{self, Params, APPLICATION_ERROR, DATABASE_ERROR, INTERNAL_ERROR, INVALID_PARAMS, UNAUTHORIZED};
+use serde_json::{Map, Value as JsonValue};
+use validator::Validate;
+
+pub const OPENRPC_VERSION: &str = "1.2.6";
+
+/// JSON Schema of `T`, e.g. `#[validate(length(min = 6))]` becomes `minLength: 6`.
+pub fn schema<T: Params>() -> JsonValue {
+    let defaults = match serde_json::to_value(T::default()) {
+        Ok(JsonValue::Object(map)) => map,
+        _ => Map::new()
+    };
+    let validation = match Validate::validate(&T::default()) {
+        Ok(_) => validator::ValidationErrors::new(),
+        Err(e) => e
+    };
+    let field_errors = validation.field_errors();
+
+    let mut properties = Map::new();
+    let mut required = vec![];
+    for field in T::FIELDS {
+        let default = defaults.get(*field).unwrap_or(&JsonValue::Null);
+        let mut property = Map::new();
+        if !default.is_null() {
+            property.insert("type".to_string(), json!(rpc::json_type(default)));
+            required.push(*field);
+        }
+
+        for error in field_errors.get(field).map(|errors| errors.iter()).into_iter().flatten() {
+            match &*error.code {
+                "length" => {
+                    if let Some(min) = error.params.get("min") {
+                        property.insert("minLength".to_string(), min.clone());
+                    }
+                    if let Some(max) = error.params.get("max") {
+                        property.insert("maxLength".to_string(), max.clone());
+                    }
+                    if let Some(equal) = error.params.get("equal") {
+                        property.insert("minLength".to_string(), equal.clone());
+                        property.insert("maxLength".to_string(), equal.clone());
+                    }
+                },
+                "range" => {
+                    if let Some(min) = error.params.get("min") {
+                        property.insert("minimum".to_string(), min.clone());
+                    }
+                    if let Some(max) = error.params.get("max") {
+                        property.insert("maximum".to_string(), max.clone());
+                    }
+                },
+                "email" => {
+                    property.insert("format".to_string(), json!("email"));
+                },
+                "url" => {
+                    property.insert("format".to_string(), json!("uri"));
+                },
+                _ => {}
+            }
+        }
+        properties.insert(field.to_string(), JsonValue::Object(property));
+    }
+
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+/// OpenRPC content descriptors for the params of a method taking `T`, in by-position order.
+pub fn params<T: Params>() -> Vec<JsonValue> {
+    let schema = schema::<T>();
+    T::FIELDS.iter()
+        .map(|field| json!({
+            "name": field,
+            "required": schema["required"].as_array().map_or(false, |required| required.contains(&json!(field))),
+            "schema": schema["properties"][*field]
+        }))
+        .collect()
+}
+
+fn errors() -> JsonValue {
+    json!({
+        "InvalidParams": {
+            "code": INVALID_PARAMS,
+            "message": "Invalid params.",
+            "data": { "validation": { "<field>": [{ "code": "<validator code>", "message": null, "params": {} }] } }
+        },
+        "InternalError": { "code": INTERNAL_ERROR, "message": "Internal error." },
+        "ApplicationError": { "code": APPLICATION_ERROR, "message": "<reason>", "data": { "application": "<reason>" } },
+        "DatabaseError": { "code": DATABASE_ERROR, "message": "Database error.", "data": { "database": "<driver message>" } },
+        "Unauthorized": { "code": UNAUTHORIZED, "message": "Unauthorized." }
+    })
+}
+
+pub fn document(config: &crate::Config, router: &Router) -> JsonValue {
+    let methods: Vec<JsonValue> = router.methods()
+        .into_iter()
+        .map(|(name, meta)| {
+            let mut errors = vec![
+                json!({ "$ref": "#/components/errors/InvalidParams" }),
+                json!({ "$ref": "#/components/errors/InternalError" }),
+                json!({ "$ref": "#/components/errors/ApplicationError" }),
+                json!({ "$ref": "#/components/errors/DatabaseError" })
+            ];
+            if meta.auth_required {
+                errors.push(json!({ "$ref": "#/components/errors/Unauthorized" }));
+            }
+            let mut method = json!({
+                "name": name,
+                "paramStructure": "either",
+                "params": meta.params,
+                "result": { "name": "result", "schema": meta.result },
+                "errors": errors,
+                "deprecated": meta.deprecated.is_some(),
+                "x-auth-required": meta.auth_required,
+                "x-rate-limit": meta.rate_limit
+            });
+            if let Some(ref summary) = meta.summary {
+                method["summary"] = json!(summary);
+            }
+            if let Some(ref reason) = meta.deprecated {
+                method["description"] = json!(format!("Deprecated: {}", reason));
+            }
+            method
+        })
+        .collect();
+
+    json!({
+        "openrpc": OPENRPC_VERSION,
+        "info": { "title": config.app_name, "version": env!("CARGO_PKG_VERSION") },
+        "methods": methods,
+        "components": { "errors": errors() }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::{SignUpDTO, SignUpWithoutPasswordDTO};
+
+    #[test]
+    fn schema_from_dto() {
+        assert_eq!(schema::<SignUpDTO>(), json!({
+            "type": "object",
+            "properties": {
+                "username": { "type": "string", "minLength": 2 },
+                "email": { "type": "string", "format": "email" },
+                "password": { "type": "string", "minLength": 6, "maxLength": 1024 }
+            },
+            "required": ["username", "email", "password"]
+        }));
+    }
+
+    #[test]
+    fn params_in_position_order() {
+        let params = params::<SignUpWithoutPasswordDTO>();
+
+        assert_eq!(params.len(), 2);
+        assert_eq!(params[0]["name"], "username");
+        assert_eq!(params[0]["required"], true);
+        assert_eq!(params[1]["name"], "email");
+        assert_eq!(params[1]["schema"]["format"], "email");
+    }
+}
@@ -0,0 +1,278 @@
+//! Resource-oriented HTTP API over the same `domain::user` operations as `api::user`.
+//!
+//! Failures are answered with `{ "error": { code, message, data } }`, using the
+//! codes documented in `api::rpc`, and an HTTP status matching the failure.
+use crate::domain::user;
+use crate::api::openrpc;
+use crate::api::rpc::{self, Params, RPCError};
+use actix_web::{web, HttpResponse};
+use actix_web::http::StatusCode;
+use serde_json::Value as JsonValue;
+
+/// `not_found` is the status used for `DTOErrors::ApplicationError`, which
+/// means something different on each resource.
+fn error(e: RPCError, not_found: StatusCode) -> HttpResponse {
+    let status = match e.code {
+        rpc::INVALID_PARAMS => StatusCode::UNPROCESSABLE_ENTITY,
+        rpc::APPLICATION_ERROR => not_found,
+        _ => StatusCode::INTERNAL_SERVER_ERROR
+    };
+    HttpResponse::build(status).json(json!({ "error": e.to_json() }))
+}
+
+fn body<T: Params>(body: &JsonValue) -> Result<T, HttpResponse> {
+    rpc::params::<T>(body).map_err(|e| error(e, StatusCode::BAD_REQUEST))
+}
+
+/// `POST /users`, without a `password` the user is emailed a link to create one.
+pub fn create_user(data: web::Data<crate::AppState>, payload: web::Json<JsonValue>) -> HttpResponse {
+    let result = if payload.get("password").is_some() {
+        let api_param: user::SignUpDTO = match body(&payload) {
+            Ok(api_param) => api_param,
+            Err(response) => return response
+        };
+        user::sign_up::run(&data.config, &data.db_conn, &api_param)
+            .map(|_| json!({ "username": api_param.username, "email": api_param.email }))
+    } else {
+        let api_param: user::SignUpWithoutPasswordDTO = match body(&payload) {
+            Ok(api_param) => api_param,
+            Err(response) => return response
+        };
+        user::sign_up_without_password::run(&data.config, &data.db_conn, &api_param)
+            .map(|_| json!({ "username": api_param.username, "email": api_param.email }))
+    };
+
+    match result {
+        Ok(user) => HttpResponse::Created().json(user),
+        // The only application error of signing up is a taken username or email.
+        Err(e) => error(RPCError::from(e), StatusCode::CONFLICT)
+    }
+}
+
+/// `POST /sessions`
+pub fn create_session(data: web::Data<crate::AppState>, payload: web::Json<JsonValue>) -> HttpResponse {
+    let api_param: user::SignInDTO = match body(&payload) {
+        Ok(api_param) => api_param,
+        Err(response) => return response
+    };
+
+    match user::sign_in::run(&data.config, &data.db_conn, &api_param) {
+        Ok(tokens) => HttpResponse::Created().json(tokens),
+        Err(e) => error(RPCError::from(e), StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// `POST /sessions/refresh`
+pub fn refresh_session(data: web::Data<crate::AppState>, payload: web::Json<JsonValue>) -> HttpResponse {
+    let api_param: user::RefreshTokenDTO = match body(&payload) {
+        Ok(api_param) => api_param,
+        Err(response) => return response
+    };
+
+    match user::refresh_token::run(&data.config, &data.db_conn, &api_param) {
+        Ok(tokens) => HttpResponse::Created().json(tokens),
+        Err(e) => error(RPCError::from(e), StatusCode::UNAUTHORIZED)
+    }
+}
+
+/// `POST /password-resets`
+pub fn create_password_reset(data: web::Data<crate::AppState>, payload: web::Json<JsonValue>) -> HttpResponse {
+    let api_param: user::ForgotMyPasswordDTO = match body(&payload) {
+        Ok(api_param) => api_param,
+        Err(response) => return response
+    };
+
+    match user::forgot_my_password::run(&data.config, &data.db_conn, &api_param) {
+        Ok(_) => HttpResponse::Accepted().finish(),
+        Err(e) => error(RPCError::from(e), StatusCode::NOT_FOUND)
+    }
+}
+
+/// `PUT /password`
+pub fn update_password(data: web::Data<crate::AppState>, payload: web::Json<JsonValue>) -> HttpResponse {
+    let api_param: user::UpdatePasswordDTO = match body(&payload) {
+        Ok(api_param) => api_param,
+        Err(response) => return response
+    };
+
+    match user::update_password::run(&data.config, &data.db_conn, &api_param) {
+        Ok(_) => HttpResponse::NoContent().finish(),
+        Err(e) => error(RPCError::from(e), StatusCode::NOT_FOUND)
+    }
+}
+
+/// `GET /identities/{id}`
+pub fn get_identity(data: web::Data<crate::AppState>, identity: web::Path<String>) -> HttpResponse {
+    let api_param: user::IdentityCheckDTO = match body(&json!({ "identity": identity.into_inner() })) {
+        Ok(api_param) => api_param,
+        Err(response) => return response
+    };
+
+    match user::identity_check::run(&data.db_conn, &api_param) {
+        Ok(_) => HttpResponse::Ok().json(json!({ "identity": api_param.identity })),
+        Err(e) => error(RPCError::from(e), StatusCode::NOT_FOUND)
+    }
+}
+
+fn request_body<T: Params>() -> JsonValue {
+    json!({ "required": true, "content": { "application/json": { "schema": openrpc::schema::<T>() } } })
+}
+
+fn response(description: &str, schema: Option<JsonValue>) -> JsonValue {
+    match schema {
+        Some(schema) => json!({ "description": description, "content": { "application/json": { "schema": schema } } }),
+        None => json!({ "description": description })
+    }
+}
+
+fn error_response(description: &str) -> JsonValue {
+    response(description, Some(json!({ "$ref": "#/components/schemas/Error" })))
+}
+
+/// OpenAPI 3 document of the routes above, served from `GET /openapi.json`.
+pub fn openapi_document(config: &crate::Config) -> JsonValue {
+    let user = json!({
+        "type": "object",
+        "properties": { "username": { "type": "string" }, "email": { "type": "string" } }
+    });
+    let tokens = json!({
+        "type": "object",
+        "properties": {
+            "access_token": { "type": "string" },
+            "refresh_token": { "type": "string" },
+            "token_type": { "type": "string", "enum": ["Bearer"] },
+            "expires_in": { "type": "integer" },
+            "id_token": { "type": "string" }
+        }
+    });
+    json!({
+        "openapi": "3.0.2",
+        "info": { "title": config.app_name, "version": env!("CARGO_PKG_VERSION") },
+        "paths": {
+            "/users": {
+                "post": {
+                    "summary": "Creates a user. Without a password the user is emailed a link to create one.",
+                    "requestBody": {
+                        "required": true,
+                        "content": { "application/json": { "schema": { "oneOf": [
+                            openrpc::schema::<user::SignUpDTO>(),
+                            openrpc::schema::<user::SignUpWithoutPasswordDTO>()
+                        ] } } }
+                    },
+                    "responses": {
+                        "201": response("User created.", Some(user)),
+                        "409": error_response("The username or email is taken."),
+                        "422": error_response("Invalid body."),
+                        "500": error_response("Database error.")
+                    }
+                }
+            },
+            "/sessions": {
+                "post": {
+                    "summary": "Exchanges a username or email and password for a JWT and a refresh token.",
+                    "requestBody": request_body::<user::SignInDTO>(),
+                    "responses": {
+                        "201": response("Signed in.", Some(tokens.clone())),
+                        "401": error_response("Incorrect username or password."),
+                        "422": error_response("Invalid body.")
+                    }
+                }
+            },
+            "/sessions/refresh": {
+                "post": {
+                    "summary": "Exchanges a refresh token for a new JWT and refresh token.",
+                    "requestBody": request_body::<user::RefreshTokenDTO>(),
+                    "responses": {
+                        "201": response("Tokens rotated.", Some(tokens)),
+                        "401": error_response("Incorrect, expired or reused refresh token."),
+                        "422": error_response("Invalid body.")
+                    }
+                }
+            },
+            "/password-resets": {
+                "post": {
+                    "summary": "Emails a password update token.",
+                    "requestBody": request_body::<user::ForgotMyPasswordDTO>(),
+                    "responses": {
+                        "202": response("Token sent.", None),
+                        "404": error_response("Username or email not found."),
+                        "422": error_response("Invalid body.")
+                    }
+                }
+            },
+            "/password": {
+                "put": {
+                    "summary": "Sets a password using a password update token.",
+                    "requestBody": request_body::<user::UpdatePasswordDTO>(),
+                    "responses": {
+                        "204": response("Password updated.", None),
+                        "404": error_response("Incorrect password update token."),
+                        "422": error_response("Invalid body.")
+                    }
+                }
+            },
+            "/identities/{id}": {
+                "get": {
+                    "summary": "Checks whether a username or email is taken.",
+                    "parameters": [{ "name": "id", "in": "path", "required": true, "schema": { "type": "string" } }],
+                    "responses": {
+                        "200": response("Identity exists.", Some(json!({
+                            "type": "object",
+                            "properties": { "identity": { "type": "string" } }
+                        }))),
+                        "404": error_response("Identity not found.")
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": {
+                "Error": {
+                    "type": "object",
+                    "properties": {
+                        "error": {
+                            "type": "object",
+                            "properties": {
+                                "code": { "type": "integer" },
+                                "message": { "type": "string" },
+                                "data": {}
+                            },
+                            "required": ["code", "message"]
+                        }
+                    }
+                }
+            }
+        }
+    })
+}
+
+/// `GET /openapi.json`
+pub fn openapi(data: web::Data<crate::AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(openapi_document(&data.config))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validation_error_status() {
+        let e = RPCError::from(user::DTOErrors::ValidationError(validator::ValidationErrors::new()));
+
+        assert_eq!(error(e, StatusCode::CONFLICT).status(), StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn application_error_status() {
+        let e = RPCError::from(user::DTOErrors::ApplicationError("Username or email is taken.".to_string()));
+
+        assert_eq!(error(e, StatusCode::CONFLICT).status(), StatusCode::CONFLICT);
+    }
+
+    #[test]
+    fn database_error_status() {
+        let e = RPCError::from(user::DTOErrors::DatabaseError("Connection refused.".to_string()));
+
+        assert_eq!(error(e, StatusCode::CONFLICT).status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,246 @@
+//! Registry of the JSON-RPC methods served by `/api`.
+//!
+//! Methods are registered by name with a `Handler` and their `MethodMeta`, so
+//! downstream code can add its own next to `api::user::register`:
+//!
+//! ```ignore
+//! router.register("app.ping", MethodMeta::new(), |_, _| Ok(json!("pong")));
+//! ```
+//!
+//! `rpc.discover` is built in and describes every registered method.
+use crate::api::openrpc;
+use crate::api::rpc::{Params, RPCError, RPCRequest, RPCResponse};
+use crate::api::ws::Hub;
+use crate::domain::user::revocation::Revocations;
+use crate::domain::user::Claims;
+use mysql as my;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::panic::{self, AssertUnwindSafe};
+
+/// What a method gets to work with besides its params.
+pub struct Context<'a> {
+    pub config: &'a crate::Config,
+    pub db_conn: &'a my::Pool,
+    pub hub: &'a Hub,
+    pub revocations: &'a Revocations,
+    /// The verified caller, always set for methods registered with `auth_required`.
+    pub claims: Option<&'a Claims>
+}
+
+pub trait Handler: Send + Sync {
+    fn call(&self, context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError>;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&Context, &JsonValue) -> Result<JsonValue, RPCError> + Send + Sync
+{
+    fn call(&self, context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+        self(context, params)
+    }
+}
+
+/// How aggressively callers of a method should be throttled.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimit {
+    /// Cheap reads, e.g. `app.identity_check`.
+    Relaxed,
+    Standard,
+    /// Methods that check credentials or send emails.
+    Strict
+}
+
+impl Default for RateLimit {
+    fn default() -> RateLimit {
+        RateLimit::Standard
+    }
+}
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MethodMeta {
+    pub auth_required: bool,
+    pub rate_limit: RateLimit,
+    /// Why the method is deprecated and what to use instead.
+    pub deprecated: Option<String>,
+    pub summary: Option<String>,
+    /// OpenRPC content descriptors of the params, see `api::openrpc::params`.
+    pub params: Vec<JsonValue>,
+    /// JSON Schema of the result.
+    pub result: JsonValue
+}
+
+impl MethodMeta {
+    pub fn new() -> MethodMeta {
+        MethodMeta::default()
+    }
+
+    pub fn auth_required(mut self) -> MethodMeta {
+        self.auth_required = true;
+        self
+    }
+
+    pub fn rate_limit(mut self, rate_limit: RateLimit) -> MethodMeta {
+        self.rate_limit = rate_limit;
+        self
+    }
+
+    pub fn deprecated(mut self, reason: &str) -> MethodMeta {
+        self.deprecated = Some(reason.to_string());
+        self
+    }
+
+    pub fn summary(mut self, summary: &str) -> MethodMeta {
+        self.summary = Some(summary.to_string());
+        self
+    }
+
+    pub fn params<T: Params>(mut self) -> MethodMeta {
+        self.params = openrpc::params::<T>();
+        self
+    }
+
+    pub fn result(mut self, schema: JsonValue) -> MethodMeta {
+        self.result = schema;
+        self
+    }
+}
+
+struct Method {
+    meta: MethodMeta,
+    handler: Box<dyn Handler>
+}
+
+#[derive(Default)]
+pub struct Router {
+    methods: BTreeMap<String, Method>
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Registers `handler` under `name`, replacing any method already registered with it.
+    pub fn register_handler<H: Handler + 'static>(&mut self, name: &str, meta: MethodMeta, handler: H) -> &mut Router {
+        self.methods.insert(name.to_string(), Method { meta, handler: Box::new(handler) });
+        self
+    }
+
+    /// Same as `register_handler` for plain functions and closures.
+    pub fn register<F>(&mut self, name: &str, meta: MethodMeta, handler: F) -> &mut Router
+    where
+        F: Fn(&Context, &JsonValue) -> Result<JsonValue, RPCError> + Send + Sync + 'static
+    {
+        self.register_handler(name, meta, handler)
+    }
+
+    /// Registered methods ordered by name.
+    pub fn methods(&self) -> Vec<(&str, &MethodMeta)> {
+        self.methods.iter()
+            .map(|(name, method)| (name.as_str(), &method.meta))
+            .collect()
+    }
+
+    pub fn call(&self, context: &Context, message: &RPCRequest) -> Result<JsonValue, RPCError> {
+        if message.method == "rpc.discover" {
+            return Ok(openrpc::document(context.config, self));
+        }
+
+        let method = match self.methods.get(&message.method) {
+            Some(method) => method,
+            None => return Err(RPCError::method_not_found())
+        };
+
+        if method.meta.auth_required && context.claims.is_none() {
+            return Err(RPCError::unauthorized());
+        }
+
+        if let Some(ref reason) = method.meta.deprecated {
+            log::warn!("Deprecated method {} called: {}", message.method, reason);
+        }
+
+        method.handler.call(context, &message.params)
+    }
+
+    /// Handles a raw payload holding a single request or a batch. Returns `None`
+    /// when there is nothing to reply, i.e. the payload only held notifications.
+    pub fn handle(&self, context: &Context, body: &str) -> Option<RPCResponse> {
+        let payload: JsonValue = match serde_json::from_str(body) {
+            Ok(payload) => payload,
+            Err(_) => return Some(RPCResponse::error(context.config, RPCError::parse_error()))
+        };
+
+        match payload {
+            JsonValue::Array(batch) => {
+                if batch.is_empty() {
+                    return Some(RPCResponse::error(context.config, RPCError::invalid_request()));
+                }
+
+                let responses: Vec<JsonValue> = batch.into_iter()
+                    .filter_map(|entry| self.handle_entry(context, entry))
+                    .map(|response| response.body)
+                    .collect();
+
+                if responses.is_empty() {
+                    return None;
+                }
+                Some(RPCResponse::batch(responses))
+            },
+            payload => self.handle_entry(context, payload)
+        }
+    }
+
+    /// A malformed request or a method that panics only produces an error reply
+    /// for that request, and notifications (no `id`) produce none.
+    fn handle_entry(&self, context: &Context, entry: JsonValue) -> Option<RPCResponse> {
+        let message = match RPCRequest::from_value(entry) {
+            Ok(message) => message,
+            Err((id, e)) => return Some(RPCResponse::new(context.config, &id, Err(e)))
+        };
+
+        let outcome = match panic::catch_unwind(AssertUnwindSafe(|| self.call(context, &message))) {
+            Ok(outcome) => outcome,
+            Err(_) => Err(RPCError::internal_error())
+        };
+
+        match message.id {
+            Some(ref id) => Some(RPCResponse::new(context.config, id, outcome)),
+            None => None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn router() -> Router {
+        let mut router = Router::new();
+        router
+            .register("app.ping", MethodMeta::new(), |_, _| Ok(json!("pong")))
+            .register("app.old_ping", MethodMeta::new().deprecated("Use app.ping instead."), |_, _| Ok(json!("pong")))
+            .register("app.whoami", MethodMeta::new().auth_required().rate_limit(RateLimit::Strict), |_, _| Ok(json!(null)));
+        router
+    }
+
+    #[test]
+    fn methods() {
+        let router = router();
+        let names: Vec<&str> = router.methods().into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(names, vec!["app.old_ping", "app.ping", "app.whoami"]);
+    }
+
+    #[test]
+    fn meta() {
+        let router = router();
+        let methods = router.methods();
+        let (_, whoami) = methods.iter().find(|(name, _)| *name == "app.whoami").unwrap();
+
+        assert!(whoami.auth_required);
+        assert_eq!(whoami.rate_limit, RateLimit::Strict);
+        assert_eq!(whoami.deprecated, None);
+    }
+}
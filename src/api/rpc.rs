@@ -0,0 +1,414 @@
+//! JSON-RPC 2.0 envelope shared by the methods registered in `api::router`.
+//!
+//! Failures are returned as `error: { code, message, data }` objects. Protocol
+//! failures use the codes from the specification, application failures use the
+//! implementation-defined range -32000 to -32099:
+//!
+//! | code   | meaning                                                     |
+//! |--------|-------------------------------------------------------------|
+//! | -32700 | parse error, the body isn't valid JSON                      |
+//! | -32600 | invalid request, the body isn't a JSON-RPC request          |
+//! | -32601 | method not found                                            |
+//! | -32602 | invalid params, `data` holds `DTOErrors::ValidationError`   |
+//! | -32603 | internal error                                              |
+//! | -32001 | `DTOErrors::ApplicationError`, `data` holds the reason      |
+//! | -32002 | `DTOErrors::DatabaseError`, `data` holds the driver message |
+//! | -32003 | unauthorized, the method needs a valid bearer token         |
+//!
+//! When `RPC_LEGACY_ERRORS=true` errors are rendered the old way instead:
+//! `result: { status: "error", errors }` served with HTTP 400/404.
+use crate::domain::user::DTOErrors;
+use actix_web::HttpResponse;
+use actix_web::http::StatusCode;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Number, Value as JsonValue};
+use std::borrow::Cow;
+use std::mem;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+pub const APPLICATION_ERROR: i64 = -32001;
+pub const DATABASE_ERROR: i64 = -32002;
+pub const UNAUTHORIZED: i64 = -32003;
+
+/// `id` of a request, echoed back in the reply with its original type.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RPCId {
+  String(String),
+  Number(Number),
+  Null
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RPCRequest {
+  /// `None` for notifications, an explicit `"id": null` is `Some(RPCId::Null)`.
+  #[serde(default, deserialize_with = "deserialize_id", skip_serializing_if = "Option::is_none")]
+  pub id: Option<RPCId>,
+  pub jsonrpc: String,
+  pub method: String,
+  #[serde(default)]
+  pub params: JsonValue
+}
+
+/// Only called when `id` is present, so `null` isn't mistaken for a notification.
+fn deserialize_id<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<RPCId>, D::Error> {
+    RPCId::deserialize(deserializer).map(Some)
+}
+
+impl RPCRequest {
+    /// Reads a request, replying with an invalid request error when it isn't a
+    /// JSON-RPC 2.0 request. The error keeps the `id` whenever it is readable.
+    pub fn from_value(entry: JsonValue) -> Result<RPCRequest, (RPCId, RPCError)> {
+        let id = entry.get("id")
+            .and_then(|id| serde_json::from_value::<RPCId>(id.clone()).ok())
+            .unwrap_or(RPCId::Null);
+
+        match serde_json::from_value::<RPCRequest>(entry) {
+            Ok(ref message) if message.jsonrpc != "2.0" => Err((id, RPCError::invalid_request()
+                .with_data(json!({ "jsonrpc": "Must be exactly \"2.0\"." })))),
+            Ok(message) => Ok(message),
+            Err(e) => Err((id, RPCError::invalid_request().with_data(json!({ "request": e.to_string() }))))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct RPCError {
+  pub code: i64,
+  pub message: String,
+  pub data: Option<JsonValue>,
+  /// Only used when rendering legacy replies.
+  pub status: StatusCode,
+  legacy_errors: Option<JsonValue>
+}
+
+impl RPCError {
+    pub fn new(code: i64, message: &str) -> RPCError {
+        let status = match code {
+            METHOD_NOT_FOUND => StatusCode::NOT_FOUND,
+            INTERNAL_ERROR => StatusCode::INTERNAL_SERVER_ERROR,
+            UNAUTHORIZED => StatusCode::UNAUTHORIZED,
+            _ => StatusCode::BAD_REQUEST
+        };
+        RPCError { code, message: message.to_string(), data: None, status, legacy_errors: None }
+    }
+
+    pub fn parse_error() -> RPCError {
+        RPCError::new(PARSE_ERROR, "Parse error.")
+    }
+
+    pub fn invalid_request() -> RPCError {
+        RPCError::new(INVALID_REQUEST, "Invalid request.")
+    }
+
+    pub fn method_not_found() -> RPCError {
+        RPCError::new(METHOD_NOT_FOUND, "Method doesn't exist.")
+    }
+
+    pub fn internal_error() -> RPCError {
+        RPCError::new(INTERNAL_ERROR, "Internal error.")
+    }
+
+    pub fn unauthorized() -> RPCError {
+        RPCError::new(UNAUTHORIZED, "Unauthorized.")
+    }
+
+    pub fn with_data(mut self, data: JsonValue) -> RPCError {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> RPCError {
+        self.status = status;
+        self
+    }
+
+    /// Overrides what legacy replies carry under `result.errors`.
+    pub fn with_legacy_errors(mut self, errors: JsonValue) -> RPCError {
+        self.legacy_errors = Some(errors);
+        self
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        match self.data {
+            Some(ref data) => json!({ "code": self.code, "message": self.message, "data": data }),
+            None => json!({ "code": self.code, "message": self.message })
+        }
+    }
+
+    fn to_legacy_json(&self) -> JsonValue {
+        match (&self.legacy_errors, &self.data) {
+            (Some(errors), _) | (None, Some(errors)) => json!({ "status": "error", "errors": errors }),
+            (None, None) => json!({ "status": "error", "error": self.message })
+        }
+    }
+}
+
+impl From<DTOErrors> for RPCError {
+    fn from(e: DTOErrors) -> RPCError {
+        let data = json!(e);
+        match e {
+            DTOErrors::ValidationError(_) => RPCError::new(INVALID_PARAMS, "Invalid params.").with_data(data),
+            DTOErrors::ApplicationError(message) => RPCError::new(APPLICATION_ERROR, &message).with_data(data),
+            DTOErrors::DatabaseError(_) => RPCError::new(DATABASE_ERROR, "Database error.").with_data(data)
+        }
+    }
+}
+
+/// Implemented by the DTOs a method takes as params. `FIELDS` gives the
+/// order used when params are passed by position instead of by name.
+pub trait Params: DeserializeOwned + Serialize + Validate + Default {
+    const FIELDS: &'static [&'static str];
+}
+
+pub fn json_type(value: &JsonValue) -> &'static str {
+    match *value {
+        JsonValue::Null => "null",
+        JsonValue::Bool(_) => "boolean",
+        JsonValue::Number(_) => "number",
+        JsonValue::String(_) => "string",
+        JsonValue::Array(_) => "array",
+        JsonValue::Object(_) => "object"
+    }
+}
+
+/// Deserializes by-name or by-position params into `T`.
+///
+/// Missing and mistyped fields are reported as `required` and `type` errors in
+/// the same shape as `validator` errors, together with the errors `validate()`
+/// finds on the remaining fields, so clients get every problem in one reply.
+pub fn params<T: Params>(params: &JsonValue) -> Result<T, RPCError> {
+    let mut given = match *params {
+        JsonValue::Object(ref map) => map.clone(),
+        JsonValue::Array(ref values) => {
+            if values.len() > T::FIELDS.len() {
+                return Err(RPCError::new(INVALID_PARAMS, "Too many params.")
+                    .with_data(json!({ "expected": T::FIELDS })));
+            }
+            T::FIELDS.iter()
+                .zip(values.iter())
+                .map(|(field, value)| (field.to_string(), value.clone()))
+                .collect()
+        },
+        JsonValue::Null => Map::new(),
+        _ => return Err(RPCError::new(INVALID_PARAMS, "Params must be an object or an array."))
+    };
+
+    // Fields that are `null` by default are optional and left to serde.
+    let defaults = match serde_json::to_value(T::default()) {
+        Ok(JsonValue::Object(map)) => map,
+        _ => Map::new()
+    };
+
+    let mut errors = ValidationErrors::new();
+    for field in T::FIELDS {
+        let expected = match defaults.get(*field) {
+            Some(expected) if !expected.is_null() => expected,
+            _ => continue
+        };
+        let error = match given.get(*field) {
+            None | Some(JsonValue::Null) => ValidationError::new("required"),
+            Some(value) if mem::discriminant(value) != mem::discriminant(expected) => {
+                let mut error = ValidationError::new("type");
+                error.add_param(Cow::from("expected"), &json_type(expected));
+                error.add_param(Cow::from("value"), value);
+                error
+            },
+            _ => continue
+        };
+        errors.add(*field, error);
+        given.insert(field.to_string(), expected.clone());
+    }
+
+    let data: T = match serde_json::from_value(JsonValue::Object(given)) {
+        Ok(data) => data,
+        Err(e) => return Err(RPCError::new(INVALID_PARAMS, "Invalid params.")
+            .with_data(json!({ "params": e.to_string() })))
+    };
+
+    if errors.errors().is_empty() {
+        return Ok(data);
+    }
+
+    if let Err(validation) = data.validate() {
+        let flagged = errors.field_errors();
+        let others: Vec<(&'static str, ValidationError)> = validation.field_errors()
+            .into_iter()
+            .filter(|(field, _)| !flagged.contains_key(field))
+            .flat_map(|(field, list)| list.iter().map(move |error| (field, error.clone())))
+            .collect();
+        for (field, error) in others {
+            errors.add(*field, error);
+        }
+    }
+    Err(RPCError::from(DTOErrors::ValidationError(errors)))
+}
+
+/// A JSON-RPC reply together with the HTTP status it is served with when it
+/// is the only reply of the request. Batch entries only keep the `body`.
+pub struct RPCResponse {
+  pub status: StatusCode,
+  pub body: JsonValue
+}
+
+impl RPCResponse {
+    pub fn new(config: &crate::Config, id: &RPCId, outcome: Result<JsonValue, RPCError>) -> RPCResponse {
+        match outcome {
+            Ok(result) => RPCResponse {
+                status: StatusCode::OK,
+                body: json!({ "jsonrpc": "2.0", "result": result, "id": id })
+            },
+            Err(ref e) if config.rpc_legacy_errors => RPCResponse {
+                status: e.status,
+                body: json!({ "jsonrpc": "2.0", "result": e.to_legacy_json(), "id": id })
+            },
+            Err(e) => RPCResponse {
+                status: StatusCode::OK,
+                body: json!({ "jsonrpc": "2.0", "error": e.to_json(), "id": id })
+            }
+        }
+    }
+
+    pub fn batch(responses: Vec<JsonValue>) -> RPCResponse {
+        RPCResponse { status: StatusCode::OK, body: JsonValue::Array(responses) }
+    }
+
+    /// Reply to a payload that couldn't be read as a request, so has no usable `id`.
+    pub fn error(config: &crate::Config, e: RPCError) -> RPCResponse {
+        RPCResponse::new(config, &RPCId::Null, Err(e))
+    }
+
+    pub fn to_http(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(&self.body)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::SignInDTO;
+
+    #[test]
+    fn string_id() {
+        let message = RPCRequest::from_value(json!({ "jsonrpc": "2.0", "method": "app.sign_in", "id": "abc" })).ok().unwrap();
+
+        assert_eq!(message.id, Some(RPCId::String("abc".to_string())));
+        assert_eq!(json!(message.id), json!("abc"));
+    }
+
+    #[test]
+    fn numeric_id() {
+        let message = RPCRequest::from_value(json!({ "jsonrpc": "2.0", "method": "app.sign_in", "id": 1 })).ok().unwrap();
+
+        assert_eq!(message.id, Some(RPCId::Number(Number::from(1))));
+        assert_eq!(json!(message.id), json!(1));
+    }
+
+    #[test]
+    fn null_id() {
+        let message = RPCRequest::from_value(json!({ "jsonrpc": "2.0", "method": "app.sign_in", "id": null })).ok().unwrap();
+
+        assert_eq!(message.id, Some(RPCId::Null));
+    }
+
+    #[test]
+    fn notification() {
+        let message = RPCRequest::from_value(json!({ "jsonrpc": "2.0", "method": "app.sign_in" })).ok().unwrap();
+
+        assert_eq!(message.id, None);
+        assert_eq!(message.params, JsonValue::Null);
+    }
+
+    #[test]
+    fn invalid_jsonrpc() {
+        let (id, e) = RPCRequest::from_value(json!({ "jsonrpc": "1.0", "method": "app.sign_in", "id": 7 })).err().unwrap();
+
+        assert_eq!(id, RPCId::Number(Number::from(7)));
+        assert_eq!(e.code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn invalid_request() {
+        let (id, e) = RPCRequest::from_value(json!({ "jsonrpc": "2.0", "id": "abc" })).err().unwrap();
+
+        assert_eq!(id, RPCId::String("abc".to_string()));
+        assert_eq!(e.code, INVALID_REQUEST);
+    }
+
+    #[test]
+    fn application_error() {
+        let e = RPCError::from(DTOErrors::ApplicationError("Incorrect token.".to_string()));
+
+        assert_eq!(e.code, APPLICATION_ERROR);
+        assert_eq!(e.to_json(), json!({
+            "code": -32001,
+            "message": "Incorrect token.",
+            "data": { "application": "Incorrect token." }
+        }));
+    }
+
+    #[test]
+    fn validation_error() {
+        let e = RPCError::from(DTOErrors::ValidationError(validator::ValidationErrors::new()));
+
+        assert_eq!(e.code, INVALID_PARAMS);
+        assert_eq!(e.status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn params_by_name() {
+        let data: SignInDTO = params(&json!({ "username_or_email": "farhan", "password": "123456" })).unwrap();
+
+        assert_eq!(data.username_or_email, "farhan");
+        assert_eq!(data.password, "123456");
+    }
+
+    #[test]
+    fn params_by_position() {
+        let data: SignInDTO = params(&json!(["farhan", "123456"])).unwrap();
+
+        assert_eq!(data.username_or_email, "farhan");
+        assert_eq!(data.password, "123456");
+    }
+
+    #[test]
+    fn params_too_many() {
+        let e = params::<SignInDTO>(&json!(["farhan", "123456", "extra"])).unwrap_err();
+
+        assert_eq!(e.code, INVALID_PARAMS);
+    }
+
+    #[test]
+    fn params_missing_and_mistyped() {
+        let e = params::<SignInDTO>(&json!({ "password": 123 })).unwrap_err();
+        let data = e.data.unwrap();
+
+        assert_eq!(e.code, INVALID_PARAMS);
+        assert_eq!(data["validation"]["username_or_email"][0]["code"], "required");
+        assert_eq!(data["validation"]["password"][0]["code"], "type");
+        assert_eq!(data["validation"]["password"][0]["params"]["expected"], "string");
+    }
+
+    #[test]
+    fn params_with_validation_errors() {
+        let e = params::<SignInDTO>(&json!({ "password": "123" })).unwrap_err();
+        let data = e.data.unwrap();
+
+        assert_eq!(data["validation"]["username_or_email"][0]["code"], "required");
+        assert_eq!(data["validation"]["password"][0]["code"], "length");
+    }
+
+    #[test]
+    fn legacy_method_not_found() {
+        let e = RPCError::method_not_found();
+
+        assert_eq!(e.status, StatusCode::NOT_FOUND);
+        assert_eq!(e.to_legacy_json(), json!({ "status": "error", "error": "Method doesn't exist." }));
+    }
+}
@@ -1,181 +1,314 @@
 use crate::domain::user;
-use mysql as my;
 use serde_json::Value as JsonValue;
-use actix_web::{web, HttpResponse, Error};
+use actix_web::http::StatusCode;
+use crate::api::rpc::{self, Params, RPCError, APPLICATION_ERROR};
+use crate::api::router::{Context, MethodMeta, RateLimit, Router};
+use crate::api::ws::Push;
 
-#[derive(Serialize, Deserialize)]
-pub struct RPCRequest {
-  pub id: String,
-  pub jsonrpc: String,
-  pub method: String,
-  pub params: JsonValue
+impl Params for user::SignUpDTO {
+    const FIELDS: &'static [&'static str] = &["username", "email", "password"];
 }
 
-pub fn sign_up(config: &crate::Config, db_conn: &my::Pool, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::SignUpDTO {
-        username: message.params["username"].as_str().unwrap().to_string(),
-        email: message.params["email"].as_str().unwrap().to_string(),
-        password: message.params["password"].as_str().unwrap().to_string()
-    };
+impl Params for user::SignUpWithoutPasswordDTO {
+    const FIELDS: &'static [&'static str] = &["username", "email"];
+}
+
+impl Params for user::SignInDTO {
+    const FIELDS: &'static [&'static str] = &["username_or_email", "password", "nonce"];
+}
+
+impl Params for user::AuthenticateDTO {
+    const FIELDS: &'static [&'static str] = &["token", "verify_user"];
+}
+
+impl Params for user::CheckPasswordStrengthDTO {
+    const FIELDS: &'static [&'static str] = &["password", "username", "email"];
+}
+
+impl Params for user::UpdatePasswordDTO {
+    const FIELDS: &'static [&'static str] = &["token", "password"];
+}
+
+impl Params for user::IdentityCheckDTO {
+    const FIELDS: &'static [&'static str] = &["identity"];
+}
+
+impl Params for user::ForgotMyPasswordDTO {
+    const FIELDS: &'static [&'static str] = &["username_or_email"];
+}
+
+impl Params for user::RefreshTokenDTO {
+    const FIELDS: &'static [&'static str] = &["refresh_token"];
+}
+
+impl Params for user::ChangePasswordDTO {
+    const FIELDS: &'static [&'static str] = &["current_password", "password", "refresh_token"];
+}
+
+impl Params for user::SignOutDTO {
+    const FIELDS: &'static [&'static str] = &["refresh_token"];
+}
+
+fn signed_in() -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": {
+            "status": { "type": "string", "enum": ["success"] },
+            "token": { "type": "string" },
+            "refresh_token": { "type": "string" },
+            "expires_in": { "type": "integer" },
+            "id_token": { "type": "string" }
+        },
+        "required": ["status", "token", "refresh_token", "expires_in"]
+    })
+}
+
+fn success() -> JsonValue {
+    json!({
+        "type": "object",
+        "properties": { "status": { "type": "string", "enum": ["success"] } },
+        "required": ["status"]
+    })
+}
+
+pub fn register(router: &mut Router) {
+    router
+        .register("app.sign_up", MethodMeta::new()
+            .summary("Creates a user with a password.")
+            .params::<user::SignUpDTO>()
+            .result(success())
+            .rate_limit(RateLimit::Strict), sign_up)
+        .register("app.sign_up_without_password", MethodMeta::new()
+            .summary("Creates a user and emails them a link to create a password.")
+            .params::<user::SignUpWithoutPasswordDTO>()
+            .result(success())
+            .rate_limit(RateLimit::Strict), sign_up_without_password)
+        .register("app.sign_in", MethodMeta::new()
+            .summary("Exchanges a username or email and password for a JWT and a refresh token.")
+            .params::<user::SignInDTO>()
+            .result(signed_in())
+            .rate_limit(RateLimit::Strict), sign_in)
+        .register("app.refresh_token", MethodMeta::new()
+            .summary("Exchanges a refresh token for a new JWT and refresh token.")
+            .params::<user::RefreshTokenDTO>()
+            .result(signed_in())
+            .rate_limit(RateLimit::Strict), refresh_token)
+        .register("app.authenticate", MethodMeta::new()
+            .summary("Checks that a JWT is valid and tells who it belongs to, optionally checking the user is still enabled.")
+            .params::<user::AuthenticateDTO>()
+            .result(json!({
+                "type": "object",
+                "properties": {
+                    "status": { "type": "string", "enum": ["success"] },
+                    "principal": {
+                        "type": "object",
+                        "properties": {
+                            "sub": { "type": "string" },
+                            "type": { "type": "string", "enum": ["user", "client"] },
+                            "username": { "type": "string" },
+                            "email": { "type": "string" },
+                            "client_id": { "type": "string" },
+                            "scope": { "type": "string" },
+                            "id": { "type": "integer" },
+                            "scopes": { "type": "array", "items": { "type": "string" } },
+                            "exp": { "type": "integer" },
+                            "expires_in": { "type": "integer" }
+                        },
+                        "required": ["sub", "type", "scopes", "exp", "expires_in"]
+                    }
+                },
+                "required": ["status", "principal"]
+            })), authenticate)
+        .register("app.update_password", MethodMeta::new()
+            .summary("Sets a password using a password update token.")
+            .params::<user::UpdatePasswordDTO>()
+            .result(success())
+            .rate_limit(RateLimit::Strict), update_password)
+        .register("app.check_password_strength", MethodMeta::new()
+            .summary("Scores a password and lists the password policy rules it breaks, with suggestions.")
+            .params::<user::CheckPasswordStrengthDTO>()
+            .result(json!({
+                "type": "object",
+                "properties": {
+                    "status": { "type": "string", "enum": ["success"] },
+                    "score": { "type": "integer", "minimum": 0, "maximum": 4 },
+                    "warning": { "type": ["string", "null"] },
+                    "suggestions": { "type": "array", "items": { "type": "string" } },
+                    "valid": { "type": "boolean" },
+                    "errors": { "type": "object" }
+                },
+                "required": ["status", "score", "suggestions", "valid", "errors"]
+            }))
+            .rate_limit(RateLimit::Relaxed), check_password_strength)
+        .register("app.identity_check", MethodMeta::new()
+            .summary("Checks whether a username or email is taken.")
+            .params::<user::IdentityCheckDTO>()
+            .result(success())
+            .rate_limit(RateLimit::Relaxed), identity_check)
+        .register("app.forgot_my_password", MethodMeta::new()
+            .summary("Emails a password update token.")
+            .params::<user::ForgotMyPasswordDTO>()
+            .result(success())
+            .rate_limit(RateLimit::Strict), forgot_my_password)
+        .register("app.change_password", MethodMeta::new()
+            .summary("Changes the caller's password, given their current one, and revokes the refresh tokens of their other sessions.")
+            .params::<user::ChangePasswordDTO>()
+            .result(success())
+            .rate_limit(RateLimit::Strict)
+            .auth_required(), change_password)
+        .register("app.sign_out", MethodMeta::new()
+            .summary("Revokes the caller's JWT and, when given, its refresh token.")
+            .params::<user::SignOutDTO>()
+            .result(success())
+            .auth_required(), sign_out)
+        .register("app.sign_out_everywhere", MethodMeta::new()
+            .summary("Revokes every JWT and refresh token of the caller.")
+            .result(success())
+            .auth_required(), sign_out_everywhere);
+}
+
+/// `app.sign_in` and `app.identity_check` used to answer application errors with
+/// HTTP 404 and the bare reason, legacy clients still rely on both.
+fn legacy_not_found(e: user::DTOErrors) -> RPCError {
+    match e {
+        user::DTOErrors::ApplicationError(e) => RPCError::new(APPLICATION_ERROR, &e)
+            .with_data(json!({ "application": e }))
+            .with_status(StatusCode::NOT_FOUND)
+            .with_legacy_errors(json!(e)),
+        _ => RPCError::from(e)
+    }
+}
+
+pub fn sign_up(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::SignUpDTO = rpc::params(params)?;
 
-    return match user::sign_up::run(config, &db_conn, &api_param) {
-        Ok(_) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success" }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => Ok(HttpResponse::BadRequest()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "error", "errors": e }),
-                "id": message.id.to_string()
-            })))
+    return match user::sign_up::run(context.config, context.db_conn, &api_param) {
+        Ok(_) => Ok(json!({ "status": "success" })),
+        Err(e) => Err(RPCError::from(e))
     };
 }
 
-pub fn sign_up_without_password(config: &crate::Config, db_conn: &my::Pool, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::SignUpWithoutPasswordDTO {
-        username: message.params["username"].as_str().unwrap().to_string(),
-        email: message.params["email"].as_str().unwrap().to_string()
+pub fn sign_up_without_password(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::SignUpWithoutPasswordDTO = rpc::params(params)?;
+
+    return match user::sign_up_without_password::run(context.config, context.db_conn, &api_param) {
+        Ok(_) => Ok(json!({ "status": "success" })),
+        Err(e) => Err(RPCError::from(e))
     };
+}
+
+pub fn sign_in(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::SignInDTO = rpc::params(params)?;
 
-    return match user::sign_up_without_password::run(config, &db_conn, &api_param) {
-        Ok(_) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success" }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => Ok(HttpResponse::BadRequest()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "error", "errors": e }),
-                "id": message.id.to_string()
-            })))
+    return match user::sign_in::run(context.config, context.db_conn, &api_param) {
+        Ok(tokens) => Ok(json!({
+            "status": "success",
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in,
+            "id_token": tokens.id_token
+        })),
+        Err(e) => Err(legacy_not_found(e))
     };
 }
 
-pub fn sign_in(config: &crate::Config, db_conn: &my::Pool, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::SignInDTO {
-        username_or_email: message.params["username_or_email"].as_str().unwrap().to_string(),
-        password: message.params["password"].as_str().unwrap().to_string()
+pub fn refresh_token(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::RefreshTokenDTO = rpc::params(params)?;
+
+    return match user::refresh_token::run(context.config, context.db_conn, &api_param) {
+        Ok(tokens) => Ok(json!({
+            "status": "success",
+            "token": tokens.access_token,
+            "refresh_token": tokens.refresh_token,
+            "expires_in": tokens.expires_in
+        })),
+        Err(e) => Err(RPCError::from(e))
     };
+}
 
-    return match user::sign_in::run(config, &db_conn, &api_param) {
-        Ok(token) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success", "token": token }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => {
-            return match e {
-                user::DTOErrors::ApplicationError(e) => Ok(HttpResponse::NotFound()
-                        .json(json!({
-                            "jsonrpc": message.jsonrpc.to_string(),
-                            "result": json!({ "status": "error", "errors": e }),
-                            "id": message.id.to_string()
-                        }))),
-                 _ => Ok(HttpResponse::BadRequest()
-                        .json(json!({
-                            "jsonrpc": message.jsonrpc.to_string(),
-                            "result": json!({ "status": "error", "errors": e }),
-                            "id": message.id.to_string()
-                        })))
-            }
-        }
+pub fn authenticate(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::AuthenticateDTO = rpc::params(params)?;
+    return match user::authenticate::run(context.config, context.db_conn, context.revocations, &api_param) {
+        Ok(identity) => Ok(json!({ "status": "success", "principal": identity })),
+        Err(e) => Err(RPCError::from(e))
     };
 }
 
-pub fn authenticate(config: &crate::Config, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::AuthenticateDTO {
-        token: message.params["token"].as_str().unwrap().to_string()
+pub fn update_password(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::UpdatePasswordDTO = rpc::params(params)?;
+    return match user::update_password::run(context.config, context.db_conn, &api_param) {
+        Ok(_) => Ok(json!({ "status": "success" })),
+        Err(e) => Err(RPCError::from(e))
     };
-    return match user::authenticate::run(config, &api_param) {
-        Ok(_) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success" }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => Ok(HttpResponse::BadRequest()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "error", "errors": e }),
-                "id": message.id.to_string()
-            })))
+}
+
+pub fn check_password_strength(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::CheckPasswordStrengthDTO = rpc::params(params)?;
+    return match user::check_password_strength::run(&context.config.password_policy, &api_param) {
+        Ok((strength, errors)) => Ok(json!({
+            "status": "success",
+            "score": strength.score,
+            "warning": strength.warning,
+            "suggestions": strength.suggestions,
+            "valid": errors.is_empty(),
+            "errors": errors
+        })),
+        Err(e) => Err(RPCError::from(e))
     };
 }
 
-pub fn update_password(config: &crate::Config, db_conn: &my::Pool, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::UpdatePasswordDTO {
-        token: message.params["token"].as_str().unwrap().to_string(),
-        password: message.params["password"].as_str().unwrap().to_string()
+pub fn identity_check(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::IdentityCheckDTO = rpc::params(params)?;
+    return match user::identity_check::run(context.db_conn, &api_param) {
+        Ok(_) => Ok(json!({ "status": "success" })),
+        Err(e) => Err(legacy_not_found(e))
     };
-    return match user::update_password::run(config, &db_conn, &api_param) {
-        Ok(_) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success" }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => Ok(HttpResponse::BadRequest()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "error", "errors": e }),
-                "id": message.id.to_string()
-            })))
+}
+
+pub fn forgot_my_password(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::ForgotMyPasswordDTO = rpc::params(params)?;
+
+    return match user::forgot_my_password::run(context.config, context.db_conn, &api_param) {
+        Ok(_) => Ok(json!({ "status": "success" })),
+        Err(e) => Err(RPCError::from(e))
     };
 }
 
-pub fn identity_check(db_conn: &my::Pool, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::IdentityCheckDTO {
-        identity: message.params["identity"].as_str().unwrap().to_string(),
+pub fn change_password(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::ChangePasswordDTO = rpc::params(params)?;
+    let claims = context.claims.ok_or_else(RPCError::unauthorized)?;
+
+    return match user::change_password::run(context.config, context.db_conn, claims, &api_param) {
+        Ok(_) => Ok(json!({ "status": "success" })),
+        Err(e) => Err(RPCError::from(e))
     };
-    return match user::identity_check::run(&db_conn, &api_param) {
-        Ok(_) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success" }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => {
-            return match e {
-                user::DTOErrors::ApplicationError(e) => Ok(HttpResponse::NotFound()
-                        .json(json!({
-                            "jsonrpc": message.jsonrpc.to_string(),
-                            "result": json!({ "status": "error", "errors": e }),
-                            "id": message.id.to_string()
-                        }))),
-                 _ => Ok(HttpResponse::BadRequest()
-                        .json(json!({
-                            "jsonrpc": message.jsonrpc.to_string(),
-                            "result": json!({ "status": "error", "errors": e }),
-                            "id": message.id.to_string()
-                        })))
+}
+
+pub fn sign_out(context: &Context, params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let api_param: user::SignOutDTO = rpc::params(params)?;
+    let claims = context.claims.ok_or_else(RPCError::unauthorized)?;
+
+    return match user::sign_out::run(context.db_conn, context.revocations, claims, &api_param) {
+        Ok(_) => {
+            if let Some(ref username) = claims.username {
+                context.hub.push(username, Push::new("session.revoked", json!({ "jti": claims.jti })));
             }
-        }
+            Ok(json!({ "status": "success" }))
+        },
+        Err(e) => Err(RPCError::from(e))
     };
 }
 
-pub fn forgot_my_password(config: &crate::Config, db_conn: &my::Pool, message: &web::Json<RPCRequest>) -> Result<HttpResponse, Error> {
-    let api_param = user::ForgotMyPasswordDTO {
-        username_or_email: message.params["username_or_email"].as_str().unwrap().to_string()
-    };
+pub fn sign_out_everywhere(context: &Context, _params: &JsonValue) -> Result<JsonValue, RPCError> {
+    let claims = context.claims.ok_or_else(RPCError::unauthorized)?;
 
-    return match user::forgot_my_password::run(config, &db_conn, &api_param) {
-        Ok(_) => Ok(HttpResponse::Ok()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "success" }),
-                "id": message.id.to_string()
-            }))),
-        Err(e) => Ok(HttpResponse::BadRequest()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "error", "errors": e }),
-                "id": message.id.to_string()
-            })))
+    return match user::sign_out_everywhere::run(context.db_conn, context.revocations, claims) {
+        Ok(_) => {
+            if let Some(ref username) = claims.username {
+                context.hub.push(username, Push::new("session.revoked", json!({})));
+            }
+            Ok(json!({ "status": "success" }))
+        },
+        Err(e) => Err(RPCError::from(e))
     };
 }
@@ -0,0 +1,40 @@
+//! `/.well-known/*` documents, https://tools.ietf.org/html/rfc8615
+use crate::domain::oauth;
+use actix_web::{web, HttpResponse};
+use serde_json::Value as JsonValue;
+
+/// `GET /.well-known/jwks.json`, public keys tokens are verified with.
+pub fn jwks(data: web::Data<crate::AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(data.config.keys.jwks())
+}
+
+/// https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderMetadata
+pub fn openid_configuration_document(config: &crate::Config) -> JsonValue {
+    let issuer = config.issuer_url.trim_end_matches('/');
+    json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/authorize", issuer),
+        "token_endpoint": format!("{}/token", issuer),
+        "device_authorization_endpoint": format!("{}/device_authorization", issuer),
+        "introspection_endpoint": format!("{}/introspect", issuer),
+        "introspection_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post"],
+        "jwks_uri": format!("{}/.well-known/jwks.json", issuer),
+        "userinfo_endpoint": format!("{}/userinfo", issuer),
+        "scopes_supported": oauth::SCOPES,
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code", "refresh_token", "client_credentials", oauth::device::GRANT_TYPE],
+        "code_challenge_methods_supported": ["S256"],
+        "token_endpoint_auth_methods_supported": ["none", "client_secret_basic", "client_secret_post"],
+        "subject_types_supported": [config.subject_type.name()],
+        "id_token_signing_alg_values_supported": [config.keys.algorithm()],
+        "claims_supported": [
+            "iss", "sub", "aud", "exp", "iat", "auth_time", "nonce",
+            "preferred_username", "email", "email_verified"
+        ]
+    })
+}
+
+/// `GET /.well-known/openid-configuration`
+pub fn openid_configuration(data: web::Data<crate::AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(openid_configuration_document(&data.config))
+}
@@ -0,0 +1,255 @@
+//! JSON-RPC over a WebSocket at `/ws`.
+//!
+//! Every text frame is handled like a `/api` body. A socket authenticates once
+//! with `app.authenticate`, after which methods that require an authenticated
+//! caller are available and the server may push JSON-RPC notifications to it:
+//!
+//! - `session.expiring`, `EXPIRY_WARNING` seconds before the token expires
+//! - `session.expired`, when the token expires and the socket loses its identity
+//! - `session.revoked`, when `app.sign_out` revokes the socket's token (`params.jti`)
+//!   or `app.sign_out_everywhere` revokes all of them, the socket loses its identity
+//! - anything else sent through `Hub::push`
+use crate::api::rpc::{self, RPCRequest};
+use crate::domain::user::{self, Claims};
+use actix::prelude::*;
+use actix_web::{web, Error, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use chrono::Utc;
+use serde_json::Value as JsonValue;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(10);
+pub const EXPIRY_WARNING: i64 = 300;
+
+/// Server-initiated JSON-RPC notification.
+#[derive(Clone)]
+pub struct Push {
+    pub method: String,
+    pub params: JsonValue
+}
+
+impl Message for Push {
+    type Result = ();
+}
+
+impl Push {
+    pub fn new(method: &str, params: JsonValue) -> Push {
+        Push { method: method.to_string(), params }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        json!({ "jsonrpc": "2.0", "method": self.method, "params": self.params })
+    }
+}
+
+/// Authenticated sockets by username. Recipients aren't `Sync`, hence the `Mutex`.
+#[derive(Default)]
+pub struct Hub {
+    sessions: Mutex<HashMap<String, Vec<(usize, Recipient<Push>)>>>,
+    next_id: AtomicUsize
+}
+
+impl Hub {
+    pub fn new() -> Hub {
+        Hub::default()
+    }
+
+    fn join(&self, username: &str, recipient: Recipient<Push>) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let mut sessions = self.sessions.lock().unwrap();
+        sessions.entry(username.to_string()).or_insert_with(Vec::new).push((id, recipient));
+        id
+    }
+
+    fn leave(&self, username: &str, id: usize) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let empty = match sessions.get_mut(username) {
+            Some(recipients) => {
+                recipients.retain(|(session_id, _)| *session_id != id);
+                recipients.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            sessions.remove(username);
+        }
+    }
+
+    /// Pushes a notification to every socket authenticated as `username`.
+    pub fn push(&self, username: &str, push: Push) {
+        let sessions = self.sessions.lock().unwrap();
+        for (_, recipient) in sessions.get(username).into_iter().flatten() {
+            if recipient.do_send(push.clone()).is_err() {
+                log::warn!("Unable to push {} to a socket of {}.", push.method, username);
+            }
+        }
+    }
+}
+
+struct Identity {
+    claims: Claims,
+    /// Only sockets of users join the hub.
+    hub_id: Option<usize>,
+    timers: Vec<SpawnHandle>
+}
+
+pub struct WsSession {
+    data: web::Data<crate::AppState>,
+    identity: Option<Identity>,
+    heartbeat: Instant
+}
+
+impl WsSession {
+    fn new(data: web::Data<crate::AppState>) -> WsSession {
+        WsSession { data, identity: None, heartbeat: Instant::now() }
+    }
+
+    fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.heartbeat) > CLIENT_TIMEOUT {
+                ctx.stop();
+                return;
+            }
+            ctx.ping("");
+        });
+    }
+
+    fn forget(&mut self, ctx: &mut ws::WebsocketContext<Self>) {
+        if let Some(identity) = self.identity.take() {
+            for timer in identity.timers {
+                ctx.cancel_future(timer);
+            }
+            if let (Some(username), Some(hub_id)) = (identity.claims.username.as_ref(), identity.hub_id) {
+                self.data.hub.leave(username, hub_id);
+            }
+        }
+    }
+
+    /// Gives the socket the identity of the last valid token sent to `app.authenticate`.
+    fn authenticate(&mut self, payload: &JsonValue, ctx: &mut ws::WebsocketContext<Self>) {
+        let entries: Vec<&JsonValue> = match *payload {
+            JsonValue::Array(ref batch) => batch.iter().collect(),
+            ref entry => vec![entry]
+        };
+
+        let claims = entries.into_iter()
+            .filter_map(|entry| RPCRequest::from_value(entry.clone()).ok())
+            .filter(|message| message.method == "app.authenticate")
+            .filter_map(|message| rpc::params::<user::AuthenticateDTO>(&message.params).ok())
+            .filter_map(|api_param| user::authenticate::claims(&self.data.config, &self.data.db_conn, &self.data.revocations, &api_param.token).ok())
+            .last();
+
+        let claims = match claims {
+            Some(claims) => claims,
+            None => return
+        };
+
+        self.forget(ctx);
+        let remaining = (claims.exp - Utc::now().timestamp()).max(0);
+        let expires_in = remaining.min(EXPIRY_WARNING);
+        let timers = vec![
+            ctx.run_later(Duration::from_secs((remaining - expires_in) as u64), move |_, ctx| {
+                ctx.text(Push::new("session.expiring", json!({ "expires_in": expires_in })).to_json().to_string());
+            }),
+            ctx.run_later(Duration::from_secs(remaining as u64), |act, ctx| {
+                act.forget(ctx);
+                ctx.text(Push::new("session.expired", json!({})).to_json().to_string());
+            })
+        ];
+        let hub_id = claims.username.as_ref().map(|username| self.data.hub.join(username, ctx.address().recipient()));
+        self.identity = Some(Identity { claims, hub_id, timers });
+    }
+}
+
+impl Actor for WsSession {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+    }
+
+    fn stopping(&mut self, ctx: &mut Self::Context) -> Running {
+        self.forget(ctx);
+        Running::Stop
+    }
+}
+
+impl Handler<Push> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, push: Push, ctx: &mut Self::Context) {
+        if push.method == "session.revoked" {
+            let revoked = match (self.identity.as_ref(), push.params.get("jti")) {
+                (Some(identity), Some(jti)) => *jti == json!(identity.claims.jti),
+                (Some(_), None) => true,
+                (None, _) => false
+            };
+            if !revoked {
+                return;
+            }
+            self.forget(ctx);
+        }
+        ctx.text(push.to_json().to_string());
+    }
+}
+
+impl StreamHandler<ws::Message, ws::ProtocolError> for WsSession {
+    fn handle(&mut self, message: ws::Message, ctx: &mut Self::Context) {
+        match message {
+            ws::Message::Ping(message) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&message);
+            },
+            ws::Message::Pong(_) => {
+                self.heartbeat = Instant::now();
+            },
+            ws::Message::Text(text) => {
+                let claims = self.identity.as_ref().map(|identity| &identity.claims);
+                let response = self.data.router.handle(&self.data.context(claims), &text);
+                if let Ok(payload) = serde_json::from_str::<JsonValue>(&text) {
+                    self.authenticate(&payload, ctx);
+                }
+                if let Some(response) = response {
+                    ctx.text(response.body.to_string());
+                }
+            },
+            ws::Message::Binary(_) => {
+                ctx.text(rpc::RPCResponse::error(&self.data.config, rpc::RPCError::parse_error()).body.to_string());
+            },
+            ws::Message::Close(_) => {
+                ctx.stop();
+            },
+            ws::Message::Nop => {}
+        }
+    }
+}
+
+/// `GET /ws`
+pub fn index(data: web::Data<crate::AppState>, req: HttpRequest, stream: web::Payload) -> Result<HttpResponse, Error> {
+    ws::start(WsSession::new(data), &req, stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_notification() {
+        let push = Push::new("session.revoked", json!({ "reason": "signed out" }));
+
+        assert_eq!(push.to_json(), json!({
+            "jsonrpc": "2.0",
+            "method": "session.revoked",
+            "params": { "reason": "signed out" }
+        }));
+    }
+
+    #[test]
+    fn push_without_sockets() {
+        Hub::new().push("farhan", Push::new("session.revoked", json!({})));
+    }
+}
@@ -1,3 +1,70 @@
+table! {
+    oauth_authorization_codes (id) {
+        id -> Integer,
+        code_hash -> Char,
+        client_id -> Integer,
+        user_id -> Integer,
+        redirect_uri -> Varchar,
+        code_challenge -> Varchar,
+        scope -> Varchar,
+        nonce -> Nullable<Varchar>,
+        auth_time -> Bigint,
+        expires_at -> Datetime,
+        date_created -> Timestamp,
+    }
+}
+
+table! {
+    oauth_clients (id) {
+        id -> Integer,
+        client_id -> Varchar,
+        name -> Varchar,
+        redirect_uris -> Text,
+        date_created -> Timestamp,
+        client_secret_hash -> Nullable<Char>,
+        scopes -> Varchar,
+    }
+}
+
+table! {
+    oauth_device_codes (id) {
+        id -> Integer,
+        device_code_hash -> Char,
+        user_code -> Char,
+        client_id -> Integer,
+        scope -> Varchar,
+        user_id -> Nullable<Integer>,
+        approved -> Nullable<Bool>,
+        approved_at -> Nullable<Bigint>,
+        interval -> Integer,
+        last_polled_at -> Nullable<Bigint>,
+        expires_at -> Datetime,
+        date_created -> Timestamp,
+    }
+}
+
+table! {
+    oauth_form_tokens (id) {
+        id -> Integer,
+        token_hash -> Char,
+        request_hash -> Char,
+        user_id -> Nullable<Integer>,
+        auth_time -> Nullable<Bigint>,
+        expires_at -> Datetime,
+        date_created -> Timestamp,
+    }
+}
+
+table! {
+    password_history (id) {
+        id -> Integer,
+        user_id -> Integer,
+        password -> Varchar,
+        salt -> Nullable<Varchar>,
+        date_created -> Timestamp,
+    }
+}
+
 table! {
     password_updates (id) {
         id -> Integer,
@@ -7,6 +74,29 @@ table! {
     }
 }
 
+table! {
+    refresh_tokens (id) {
+        id -> Integer,
+        user_id -> Integer,
+        family -> Varchar,
+        token_hash -> Char,
+        used -> Bool,
+        revoked -> Bool,
+        expires_at -> Datetime,
+        date_created -> Timestamp,
+        audience -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    revoked_tokens (jti) {
+        jti -> Varchar,
+        user_id -> Integer,
+        expires_at -> Datetime,
+        date_created -> Timestamp,
+    }
+}
+
 table! {
     users (id) {
         id -> Integer,
@@ -17,12 +107,30 @@ table! {
         enabled -> Bool,
         date_created -> Timestamp,
         date_update -> Timestamp,
+        tokens_revoked_before -> Nullable<Bigint>,
+        email_verified -> Bool,
+        subject -> Varchar,
     }
 }
 
+joinable!(oauth_authorization_codes -> oauth_clients (client_id));
+joinable!(oauth_authorization_codes -> users (user_id));
+joinable!(oauth_device_codes -> oauth_clients (client_id));
+joinable!(oauth_device_codes -> users (user_id));
+joinable!(oauth_form_tokens -> users (user_id));
+joinable!(password_history -> users (user_id));
 joinable!(password_updates -> users (user_id));
+joinable!(refresh_tokens -> users (user_id));
+joinable!(revoked_tokens -> users (user_id));
 
 allow_tables_to_appear_in_same_query!(
+    oauth_authorization_codes,
+    oauth_clients,
+    oauth_device_codes,
+    oauth_form_tokens,
+    password_history,
     password_updates,
+    refresh_tokens,
+    revoked_tokens,
     users,
 );
@@ -0,0 +1,171 @@
+use validator::{Validate};
+use mysql as my;
+use crate::domain::user::{generate_random, hash_token};
+use crate::domain::oauth::{client as find_client, AuthorizeDTO, Client, OAuthError, SCOPES};
+
+/// Seconds a client has to exchange a code.
+pub const CODE_EXPIRY: i64 = 300;
+
+/// Seconds the user has to submit a sign in or consent form.
+pub const FORM_EXPIRY: i64 = 600;
+
+/// The client and its redirect URI, failures here must be shown to the user
+/// rather than redirected, https://tools.ietf.org/html/rfc6749#section-4.1.2.1
+pub fn client(db_conn: &my::Pool, data: &AuthorizeDTO) -> Result<Client, OAuthError> {
+    let client = find_client(db_conn, &data.client_id)?;
+    if !client.redirect_uris.contains(&data.redirect_uri) {
+        return Err(OAuthError::invalid_request("Unregistered redirect_uri."));
+    }
+    return Ok(client);
+}
+
+/// The rest of the request, failures here are redirected back to the client.
+pub fn check(data: &AuthorizeDTO) -> Result<(), OAuthError> {
+    data.validate()?;
+    if data.response_type != "code" {
+        return Err(OAuthError::new("unsupported_response_type", "Only the code response type is supported."));
+    }
+    if data.code_challenge_method != "S256" {
+        return Err(OAuthError::invalid_request("Only S256 code challenges are supported."));
+    }
+    let scope = data.scope.as_ref().map_or("", |scope| scope.as_str());
+    if scope.split_whitespace().any(|scope| !SCOPES.contains(&scope)) {
+        return Err(OAuthError::new("invalid_scope", &format!("Supported scopes are {}.", SCOPES.join(" "))));
+    }
+    return Ok(());
+}
+
+/// Ties form tokens to every parameter of the request they were shown for.
+fn request_hash(data: &AuthorizeDTO) -> String {
+    return hash_token(&serde_json::to_string(data).unwrap_or_default());
+}
+
+/// A single use token for the sign in form, or for the consent form once the
+/// user in `signed_in` signed in, https://tools.ietf.org/html/rfc6749#section-10.12
+pub fn form_token(db_conn: &my::Pool, data: &AuthorizeDTO, signed_in: Option<(usize, i64)>) -> Result<String, OAuthError> {
+    let token = generate_random(32);
+    db_conn.prep_exec(r"
+        INSERT INTO oauth_form_tokens (token_hash, request_hash, user_id, auth_time, expires_at)
+        VALUES (:token_hash, :request_hash, :user_id, :auth_time, DATE_ADD(NOW(), INTERVAL :expiry SECOND))", params!{
+            "token_hash" => hash_token(&token),
+            "request_hash" => request_hash(data),
+            "user_id" => signed_in.map(|(user_id, _)| user_id),
+            "auth_time" => signed_in.map(|(_, auth_time)| auth_time),
+            "expiry" => FORM_EXPIRY
+        }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    return Ok(token);
+}
+
+/// Spends a token of `form_token`, returns who signed in when it belongs to a consent form.
+pub fn redeem_form_token(db_conn: &my::Pool, data: &AuthorizeDTO, token: &str) -> Result<Option<(usize, i64)>, OAuthError> {
+    let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false))
+        .map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    let result: Vec<(usize, Option<usize>, Option<i64>)> = transaction.prep_exec(r"
+        SELECT id, user_id, auth_time FROM oauth_form_tokens
+        WHERE token_hash = :token_hash AND request_hash = :request_hash AND expires_at > NOW()
+        FOR UPDATE", params!{
+            "token_hash" => hash_token(token),
+            "request_hash" => request_hash(data)
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (id, user_id, auth_time) = my::from_row(row);
+                (id, user_id, auth_time)
+            }).collect()
+        }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    if result.is_empty() {
+        return Err(OAuthError::invalid_request("Expired or already submitted form."));
+    }
+
+    let (id, user_id, auth_time) = result[0];
+    transaction.prep_exec(r"DELETE FROM oauth_form_tokens WHERE id = :id", params!{
+        "id" => &id
+    }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    match transaction.commit() {
+        Ok(_) => return Ok(user_id.and_then(|user_id| auth_time.map(|auth_time| (user_id, auth_time)))),
+        Err(e) => return Err(OAuthError::server_error(&e.to_string()))
+    }
+}
+
+/// Issues a code for `user_id`, who signed in at `auth_time`.
+pub fn run(db_conn: &my::Pool, data: &AuthorizeDTO, user_id: usize, auth_time: i64) -> Result<String, OAuthError> {
+    let client = client(db_conn, data)?;
+    check(data)?;
+
+    let code = generate_random(32);
+    let scope: Vec<&str> = data.scope.as_ref().map_or("", |scope| scope.as_str()).split_whitespace().collect();
+    db_conn.prep_exec(r"
+        INSERT INTO oauth_authorization_codes
+            (code_hash, client_id, user_id, redirect_uri, code_challenge, scope, nonce, auth_time, expires_at)
+        VALUES
+            (:code_hash, :client_id, :user_id, :redirect_uri, :code_challenge, :scope, :nonce, :auth_time,
+             DATE_ADD(NOW(), INTERVAL :expiry SECOND))", params!{
+            "code_hash" => hash_token(&code),
+            "client_id" => &client.id,
+            "user_id" => &user_id,
+            "redirect_uri" => &data.redirect_uri,
+            "code_challenge" => &data.code_challenge,
+            "scope" => scope.join(" "),
+            "nonce" => &data.nonce,
+            "auth_time" => &auth_time,
+            "expiry" => CODE_EXPIRY
+        }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    return Ok(code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> AuthorizeDTO {
+        AuthorizeDTO {
+            response_type: "code".to_string(),
+            client_id: "web".to_string(),
+            redirect_uri: "https://example.com/callback".to_string(),
+            scope: Some("openid email".to_string()),
+            state: Some("xyz".to_string()),
+            code_challenge: "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_string(),
+            code_challenge_method: "S256".to_string(),
+            nonce: None
+        }
+    }
+
+    #[test]
+    fn valid_request() {
+        assert_eq!(check(&request()), Ok(()));
+    }
+
+    #[test]
+    fn plain_code_challenge() {
+        let data = AuthorizeDTO { code_challenge_method: "plain".to_string(), ..request() };
+
+        assert_eq!(check(&data).unwrap_err().error, "invalid_request");
+    }
+
+    #[test]
+    fn unsupported_response_type() {
+        let data = AuthorizeDTO { response_type: "token".to_string(), ..request() };
+
+        assert_eq!(check(&data).unwrap_err().error, "unsupported_response_type");
+    }
+
+    #[test]
+    fn request_bound_form_tokens() {
+        let data = AuthorizeDTO { redirect_uri: "https://example.com/other".to_string(), ..request() };
+
+        assert_eq!(request_hash(&request()), request_hash(&request()));
+        assert_ne!(request_hash(&request()), request_hash(&data));
+    }
+
+    #[test]
+    fn unknown_scope() {
+        let data = AuthorizeDTO { scope: Some("openid admin".to_string()), ..request() };
+
+        assert_eq!(check(&data).unwrap_err().error, "invalid_scope");
+    }
+}
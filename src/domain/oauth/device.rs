@@ -0,0 +1,329 @@
+//! Device authorization grant, https://tools.ietf.org/html/rfc8628
+//!
+//! A device that can't host a browser redirect gets a `device_code` and a
+//! `user_code` from `authorize`, shows the user code and `DEVICE_VERIFICATION_URL`,
+//! and polls `token::run` with the device code. The user signs in on another
+//! device, checks which client asked with `describe`, i.e. `app.describe_device`,
+//! and approves the user code with `approve`, i.e. `app.approve_device`.
+use validator::{Validate};
+use mysql as my;
+use rand::Rng;
+use rand::thread_rng;
+use chrono::{Utc};
+use crate::domain::user::{generate_random, hash_token, Claims, Tokens, UserInfo, DTOErrors};
+use crate::domain::oauth::{authenticate_if_confidential, required, user_tokens, OAuthError, TokenDTO, SCOPES};
+
+pub const GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Seconds a user has to approve a user code.
+pub const EXPIRY: i64 = 600;
+/// Seconds a device waits between polls, raised by 5 on every `slow_down`.
+pub const INTERVAL: i64 = 5;
+/// No vowels, so user codes don't spell words, and no look-alike characters,
+/// https://tools.ietf.org/html/rfc8628#section-6.1
+const USER_CODE_CHARACTERS: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+/// New codes are drawn this many times when the user code is taken.
+const ATTEMPTS: usize = 5;
+
+/// Body of `POST /device_authorization`, https://tools.ietf.org/html/rfc8628#section-3.1
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DeviceAuthorizationDTO {
+    #[validate(length(min = 1))]
+    pub client_id: String,
+
+    pub client_secret: Option<String>,
+
+    pub scope: Option<String>
+}
+
+/// https://tools.ietf.org/html/rfc8628#section-3.2
+#[derive(Debug, Serialize)]
+pub struct DeviceAuthorization {
+    pub device_code: String,
+    pub user_code: String,
+    pub verification_uri: String,
+    pub verification_uri_complete: String,
+    pub expires_in: i64,
+    pub interval: i64
+}
+
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+pub struct DescribeDeviceDTO {
+    #[validate(length(min = 8))]
+    pub user_code: String
+}
+
+/// The client behind a pending user code, shown before the user approves it,
+/// https://tools.ietf.org/html/rfc8628#section-5.4
+#[derive(Debug, PartialEq, Serialize)]
+pub struct DeviceRequest {
+    pub client_id: String,
+    pub client_name: String,
+    pub scope: String
+}
+
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+pub struct ApproveDeviceDTO {
+    #[validate(length(min = 8))]
+    pub user_code: String,
+
+    /// `false` denies the device, defaults to approving it.
+    pub approve: Option<bool>
+}
+
+fn user_code() -> String {
+    let mut rng = thread_rng();
+    return (0..8)
+        .map(|_| USER_CODE_CHARACTERS[rng.gen_range(0, USER_CODE_CHARACTERS.len())] as char)
+        .collect();
+}
+
+/// `BCDF-GHJK`, `bcdfghjk` and `BCDF GHJK` are the same code.
+fn normalize_user_code(user_code: &str) -> String {
+    return user_code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+}
+
+fn display_user_code(user_code: &str) -> String {
+    return format!("{}-{}", &user_code[..4], &user_code[4..]);
+}
+
+/// Whether the codes of attempt `attempt` were taken and another one is left.
+fn retry(e: &my::Error, attempt: usize) -> bool {
+    match e {
+        my::Error::MySqlError(ref e) => e.code == 1062 && attempt < ATTEMPTS,
+        _ => false
+    }
+}
+
+pub fn authorize(config: &crate::Config, db_conn: &my::Pool, data: &DeviceAuthorizationDTO) -> Result<DeviceAuthorization, OAuthError> {
+    data.validate()?;
+    let client = authenticate_if_confidential(db_conn, &data.client_id, &data.client_secret)?;
+    let scope: Vec<&str> = data.scope.as_ref().map_or("", |scope| scope.as_str()).split_whitespace().collect();
+    if scope.iter().any(|scope| !SCOPES.contains(scope)) {
+        return Err(OAuthError::new("invalid_scope", &format!("Supported scopes are {}.", SCOPES.join(" "))));
+    }
+
+    let mut attempt = 1;
+    let (device_code, user_code) = loop {
+        let device_code = generate_random(32);
+        let user_code = user_code();
+        let result = db_conn.prep_exec(r"
+            INSERT INTO oauth_device_codes (device_code_hash, user_code, client_id, scope, `interval`, expires_at)
+            VALUES (:device_code_hash, :user_code, :client_id, :scope, :interval, DATE_ADD(NOW(), INTERVAL :expiry SECOND))", params!{
+                "device_code_hash" => hash_token(&device_code),
+                "user_code" => &user_code,
+                "client_id" => &client.id,
+                "scope" => scope.join(" "),
+                "interval" => INTERVAL,
+                "expiry" => EXPIRY
+            });
+
+        match result {
+            Ok(_) => break (device_code, user_code),
+            Err(ref e) if retry(e, attempt) => attempt += 1,
+            Err(e) => return Err(OAuthError::server_error(&e.to_string()))
+        }
+    };
+
+    let verification_uri = config.device_verification_url.to_string();
+    let separator = if verification_uri.contains('?') { "&" } else { "?" };
+    return Ok(DeviceAuthorization {
+        device_code,
+        verification_uri_complete: format!("{}{}user_code={}", verification_uri, separator, display_user_code(&user_code)),
+        user_code: display_user_code(&user_code),
+        verification_uri,
+        expires_in: EXPIRY,
+        interval: INTERVAL
+    });
+}
+
+/// The client and scope of a pending user code.
+pub fn describe(db_conn: &my::Pool, data: &DescribeDeviceDTO) -> Result<DeviceRequest, DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let result: Vec<DeviceRequest> = db_conn.prep_exec(r"
+                SELECT c.client_id, c.name, dc.scope
+                FROM oauth_device_codes dc
+                INNER JOIN oauth_clients c ON c.id = dc.client_id
+                WHERE dc.user_code = :user_code AND dc.approved IS NULL AND dc.expires_at > NOW()", params!{
+                    "user_code" => normalize_user_code(&data.user_code)
+                }).map(|result| {
+                    result.map(|x| x.unwrap()).map(|row| {
+                        // ⚠️ Note that from_row will panic if you don't follow your schema
+                        let (client_id, client_name, scope) = my::from_row(row);
+                        DeviceRequest { client_id, client_name, scope }
+                    }).collect()
+                }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+            match result.into_iter().next() {
+                Some(request) => return Ok(request),
+                None => return Err(DTOErrors::ApplicationError("Incorrect or expired user code.".to_string()))
+            }
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
+    };
+}
+
+/// Approves or denies a pending user code on behalf of the user `claims` belong to.
+pub fn approve(db_conn: &my::Pool, claims: &Claims, data: &ApproveDeviceDTO) -> Result<bool, DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let username = claims.username()?;
+            let result = db_conn.prep_exec(r"
+                UPDATE oauth_device_codes dc
+                INNER JOIN users u ON u.username = :username AND u.enabled = 1
+                SET dc.user_id = u.id, dc.approved = :approved, dc.approved_at = UNIX_TIMESTAMP()
+                WHERE dc.user_code = :user_code AND dc.approved IS NULL AND dc.expires_at > NOW()", params!{
+                    "username" => username,
+                    "approved" => data.approve.unwrap_or(true),
+                    "user_code" => normalize_user_code(&data.user_code)
+                });
+
+            match result {
+                Ok(result) if result.affected_rows() == 0 => return Err(DTOErrors::ApplicationError("Incorrect or expired user code.".to_string())),
+                Ok(_) => return Ok(true),
+                Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+            }
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
+    };
+}
+
+/// Polled by the device, https://tools.ietf.org/html/rfc8628#section-3.4
+pub fn poll(config: &crate::Config, db_conn: &my::Pool, data: &TokenDTO) -> Result<Tokens, OAuthError> {
+    let device_code = required(&data.device_code, "device_code")?;
+    let client_id = required(&data.client_id, "client_id")?;
+    authenticate_if_confidential(db_conn, client_id, &data.client_secret)?;
+
+    let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false))
+        .map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    let result: Vec<(usize, String, String, Option<bool>, Option<i64>, i64, Option<i64>, bool)> = transaction.prep_exec(r"
+        SELECT dc.id, c.client_id, dc.scope, dc.approved, dc.approved_at, dc.`interval`, dc.last_polled_at, dc.expires_at > NOW()
+        FROM oauth_device_codes dc
+        INNER JOIN oauth_clients c ON c.id = dc.client_id
+        WHERE dc.device_code_hash = :device_code_hash
+        FOR UPDATE", params!{
+            "device_code_hash" => hash_token(device_code)
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (id, client_id, scope, approved, approved_at, interval, last_polled_at, unexpired) = my::from_row(row);
+                (id, client_id, scope, approved, approved_at, interval, last_polled_at, unexpired)
+            }).collect()
+        }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    if result.is_empty() {
+        return Err(OAuthError::invalid_grant("Incorrect device_code."));
+    }
+
+    let (id, code_client_id, scope, approved, approved_at, interval, last_polled_at, unexpired) = result[0].clone();
+    if code_client_id != client_id {
+        return Err(OAuthError::invalid_grant("The device_code was issued to another client."));
+    }
+
+    let now = Utc::now().timestamp();
+    let polled = match (unexpired, approved, last_polled_at) {
+        (false, _, _) => Err(OAuthError::new("expired_token", "The device_code has expired.")),
+        (true, Some(false), _) => Err(OAuthError::new("access_denied", "The user denied the request.")),
+        (true, None, Some(last_polled_at)) if now - last_polled_at < interval => {
+            Err(OAuthError::new("slow_down", &format!("Poll at most every {} seconds.", interval + INTERVAL)))
+        },
+        (true, None, _) => Err(OAuthError::new("authorization_pending", "The user hasn't approved the request yet.")),
+        (true, Some(true), _) => Ok(())
+    };
+
+    let tokens = match polled {
+        Ok(_) => {
+            let user: Vec<(usize, String, String, String, bool)> = transaction.prep_exec(r"
+                SELECT u.id, u.subject, u.username, u.email, u.email_verified
+                FROM oauth_device_codes dc
+                INNER JOIN users u ON u.id = dc.user_id
+                WHERE dc.id = :id AND u.enabled = 1", params!{
+                    "id" => &id
+                }).map(|result| {
+                    result.map(|x| x.unwrap()).map(|row| {
+                        // ⚠️ Note that from_row will panic if you don't follow your schema
+                        let (user_id, subject, username, email, email_verified) = my::from_row(row);
+                        (user_id, subject, username, email, email_verified)
+                    }).collect()
+                }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+            match user.first().cloned() {
+                Some((user_id, subject, username, email, email_verified)) => {
+                    let user = UserInfo { sub: subject, preferred_username: username, email, email_verified };
+                    user_tokens(config, &mut transaction, user_id, user, client_id, &scope, None, approved_at.unwrap_or(now))
+                },
+                None => Err(OAuthError::new("access_denied", "The user has been disabled."))
+            }
+        },
+        Err(e) => Err(e)
+    };
+
+    // Device codes can only be exchanged once, denied and expired ones are done too.
+    match tokens {
+        Err(OAuthError { error: "authorization_pending", .. }) => {
+            transaction.prep_exec(r"UPDATE oauth_device_codes SET last_polled_at = :now WHERE id = :id", params!{
+                "now" => &now,
+                "id" => &id
+            }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+        },
+        Err(OAuthError { error: "slow_down", .. }) => {
+            transaction.prep_exec(r"
+                UPDATE oauth_device_codes SET last_polled_at = :now, `interval` = `interval` + :increase WHERE id = :id", params!{
+                    "now" => &now,
+                    "increase" => INTERVAL,
+                    "id" => &id
+                }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+        },
+        _ => {
+            transaction.prep_exec(r"DELETE FROM oauth_device_codes WHERE id = :id", params!{
+                "id" => &id
+            }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+        }
+    };
+
+    match transaction.commit() {
+        Ok(_) => return tokens,
+        Err(e) => return Err(OAuthError::server_error(&e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generated_user_code() {
+        let code = user_code();
+
+        assert_eq!(code.len(), 8);
+        assert!(code.bytes().all(|c| USER_CODE_CHARACTERS.contains(&c)));
+    }
+
+    #[test]
+    fn normalized_user_code() {
+        assert_eq!(normalize_user_code("bcdf-ghjk"), "BCDFGHJK");
+        assert_eq!(normalize_user_code(" BCDF GHJK "), "BCDFGHJK");
+    }
+
+    #[test]
+    fn taken_user_code() {
+        let e = my::Error::MySqlError(my::MySqlError {
+            state: "23000".to_string(),
+            message: "Duplicate entry 'BCDFGHJK' for key 'user_code'".to_string(),
+            code: 1062
+        });
+
+        assert!(retry(&e, 1));
+        assert!(!retry(&e, ATTEMPTS));
+    }
+
+    #[test]
+    fn displayed_user_code() {
+        assert_eq!(display_user_code("BCDFGHJK"), "BCDF-GHJK");
+    }
+}
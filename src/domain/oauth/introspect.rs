@@ -0,0 +1,116 @@
+//! Token introspection for resource servers, https://tools.ietf.org/html/rfc7662
+//!
+//! Tokens are checked exactly like `user::authenticate::run` checks them,
+//! revocations included. Only confidential clients may introspect, and every
+//! token that doesn't verify is answered with `{"active": false}` without a reason.
+use validator::{Validate};
+use mysql as my;
+use crate::domain::user::revocation::Revocations;
+use crate::domain::user::{authenticate, Claims, DTOErrors};
+use crate::domain::oauth::{authenticate_client, required, OAuthError};
+
+/// Body of `POST /introspect`, https://tools.ietf.org/html/rfc7662#section-2.1
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IntrospectDTO {
+    #[validate(length(min = 1))]
+    pub token: String,
+
+    /// Ignored, only access tokens can be introspected.
+    pub token_type_hint: Option<String>,
+
+    pub client_id: Option<String>,
+
+    pub client_secret: Option<String>
+}
+
+/// https://tools.ietf.org/html/rfc7662#section-2.2
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Introspection {
+    pub active: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sub: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exp: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub iat: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_type: Option<&'static str>
+}
+
+impl From<Claims> for Introspection {
+    fn from(claims: Claims) -> Introspection {
+        Introspection {
+            active: true,
+            sub: Some(claims.sub),
+            username: claims.username,
+            exp: Some(claims.exp),
+            iat: Some(claims.iat),
+            scope: claims.scope,
+            client_id: claims.client_id,
+            token_type: Some("Bearer")
+        }
+    }
+}
+
+/// `client_id` and `client_secret` of the resource server.
+fn client_credentials(data: &IntrospectDTO) -> Result<(&str, &str), OAuthError> {
+    let client_id = required(&data.client_id, "client_id")?;
+    let client_secret = required(&data.client_secret, "client_secret")
+        .map_err(|_| OAuthError::invalid_client("Only confidential clients can introspect tokens."))?;
+    return Ok((client_id, client_secret));
+}
+
+pub fn run(config: &crate::Config, db_conn: &my::Pool, revocations: &Revocations, data: &IntrospectDTO) -> Result<Introspection, OAuthError> {
+    let (client_id, client_secret) = client_credentials(data)?;
+    authenticate_client(db_conn, client_id, client_secret)?;
+    data.validate()?;
+
+    match authenticate::claims(config, db_conn, revocations, &data.token) {
+        Ok(claims) => return Ok(Introspection::from(claims)),
+        Err(DTOErrors::DatabaseError(e)) => return Err(OAuthError::server_error(&e)),
+        Err(_) => return Ok(Introspection::default())
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inactive() {
+        assert_eq!(serde_json::to_value(Introspection::default()).unwrap(), json!({ "active": false }));
+    }
+
+    #[test]
+    fn active_client_token() {
+        let claims = Claims {
+            client_id: Some("mailer".to_string()),
+            scope: Some("email".to_string()),
+            ..crate::domain::user::claims()
+        };
+
+        assert_eq!(serde_json::to_value(Introspection::from(claims)).unwrap(), json!({
+            "active": true,
+            "sub": "authentication",
+            "exp": 1000,
+            "iat": 100,
+            "scope": "email",
+            "client_id": "mailer",
+            "token_type": "Bearer"
+        }));
+    }
+
+    #[test]
+    fn missing_client_secret() {
+        let data = IntrospectDTO { token: "abc".to_string(), client_id: Some("mailer".to_string()), ..Default::default() };
+
+        assert_eq!(client_credentials(&data).unwrap_err().error, "invalid_client");
+    }
+}
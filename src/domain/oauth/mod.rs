@@ -0,0 +1,256 @@
+//! OAuth 2.0 authorization server over the users of `domain::user`,
+//! https://tools.ietf.org/html/rfc6749
+//!
+//! Clients get an authorization code from `authorize::run` once the user has
+//! signed in, and exchange it with `token::run` for the same tokens
+//! `user::sign_in::run` issues. Codes are bound to a PKCE S256 challenge,
+//! https://tools.ietf.org/html/rfc7636
+//!
+//! Confidential clients authenticate with a `client_secret`, of which only the
+//! SHA-256 hash is stored, and can get tokens of their own with the
+//! client_credentials grant. Those tokens carry the `client_id` and granted
+//! `scope` instead of a username and email.
+//!
+//! Resource servers that can't verify tokens themselves ask `introspect::run`.
+pub mod authorize;
+pub mod device;
+pub mod introspect;
+pub mod token;
+
+use validator::{Validate, ValidationErrors};
+use mysql as my;
+use sha2::{Digest, Sha256};
+use crate::domain::user::{access_token, generate_random, hash_token, id_token, refresh_token, tokens, DTOErrors, Tokens, UserInfo};
+
+/// Scopes a client may request, `openid` adds an ID token to the tokens.
+pub const SCOPES: &[&str] = &["openid", "profile", "email"];
+
+/// Error response, https://tools.ietf.org/html/rfc6749#section-5.2
+#[derive(PartialEq, Debug, Serialize)]
+pub struct OAuthError {
+    pub error: &'static str,
+    pub error_description: String
+}
+
+impl OAuthError {
+    pub fn new(error: &'static str, error_description: &str) -> OAuthError {
+        OAuthError { error, error_description: error_description.to_string() }
+    }
+
+    pub fn invalid_request(error_description: &str) -> OAuthError {
+        OAuthError::new("invalid_request", error_description)
+    }
+
+    pub fn invalid_client(error_description: &str) -> OAuthError {
+        OAuthError::new("invalid_client", error_description)
+    }
+
+    pub fn invalid_grant(error_description: &str) -> OAuthError {
+        OAuthError::new("invalid_grant", error_description)
+    }
+
+    pub fn server_error(error_description: &str) -> OAuthError {
+        OAuthError::new("server_error", error_description)
+    }
+}
+
+impl From<ValidationErrors> for OAuthError {
+    fn from(e: ValidationErrors) -> OAuthError {
+        let mut fields: Vec<&str> = e.field_errors().keys().cloned().collect();
+        fields.sort();
+        OAuthError::invalid_request(&format!("Invalid {}.", fields.join(", ")))
+    }
+}
+
+impl From<DTOErrors> for OAuthError {
+    fn from(e: DTOErrors) -> OAuthError {
+        match e {
+            DTOErrors::ValidationError(e) => OAuthError::from(e),
+            DTOErrors::ApplicationError(e) => OAuthError::new("access_denied", &e),
+            DTOErrors::DatabaseError(e) => OAuthError::server_error(&e)
+        }
+    }
+}
+
+/// Query of `GET /authorize`, https://tools.ietf.org/html/rfc6749#section-4.1.1
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AuthorizeDTO {
+    #[validate(length(min = 1))]
+    pub response_type: String,
+
+    #[validate(length(min = 1))]
+    pub client_id: String,
+
+    #[validate(length(min = 1))]
+    pub redirect_uri: String,
+
+    pub scope: Option<String>,
+
+    pub state: Option<String>,
+
+    /// base64url of a SHA-256, https://tools.ietf.org/html/rfc7636#section-4.2
+    #[validate(length(equal = 43))]
+    pub code_challenge: String,
+
+    #[validate(length(min = 1))]
+    pub code_challenge_method: String,
+
+    pub nonce: Option<String>
+}
+
+/// Body of `POST /token`, https://tools.ietf.org/html/rfc6749#section-4.1.3
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TokenDTO {
+    #[validate(length(min = 1))]
+    pub grant_type: String,
+
+    pub code: Option<String>,
+
+    pub redirect_uri: Option<String>,
+
+    pub client_id: Option<String>,
+
+    #[validate(length(min = 43, max = 128))]
+    pub code_verifier: Option<String>,
+
+    pub refresh_token: Option<String>,
+
+    /// From the body or `Authorization: Basic`, https://tools.ietf.org/html/rfc6749#section-2.3.1
+    pub client_secret: Option<String>,
+
+    pub scope: Option<String>,
+
+    /// https://tools.ietf.org/html/rfc8628#section-3.4
+    pub device_code: Option<String>
+}
+
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub id: usize,
+    pub client_id: String,
+    pub name: String,
+    pub redirect_uris: Vec<String>,
+    pub client_secret_hash: Option<String>,
+    /// Scopes the client may request with the client_credentials grant.
+    pub scopes: Vec<String>
+}
+
+pub fn client(db_conn: &my::Pool, client_id: &str) -> Result<Client, OAuthError> {
+    let result: Vec<(usize, String, String, String, Option<String>, String)> = db_conn.prep_exec(r"
+        SELECT id, client_id, name, redirect_uris, client_secret_hash, scopes
+        FROM oauth_clients WHERE client_id = :client_id", params!{
+            "client_id" => client_id
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (id, client_id, name, redirect_uris, client_secret_hash, scopes) = my::from_row(row);
+                (id, client_id, name, redirect_uris, client_secret_hash, scopes)
+            }).collect()
+        }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    if result.is_empty() {
+        return Err(OAuthError::invalid_client("Unknown client."));
+    }
+
+    let (id, client_id, name, redirect_uris, client_secret_hash, scopes) = result[0].clone();
+    return Ok(Client {
+        id,
+        client_id,
+        name,
+        redirect_uris: redirect_uris.split_whitespace().map(|uri| uri.to_string()).collect(),
+        client_secret_hash,
+        scopes: scopes.split_whitespace().map(|scope| scope.to_string()).collect()
+    });
+}
+
+/// A confidential client whose `client_secret` matches.
+pub fn authenticate_client(db_conn: &my::Pool, client_id: &str, client_secret: &str) -> Result<Client, OAuthError> {
+    let client = client(db_conn, client_id)?;
+    let matches = match client.client_secret_hash {
+        Some(ref client_secret_hash) => constant_time_eq(hash_token(client_secret).as_bytes(), client_secret_hash.as_bytes()),
+        None => false
+    };
+    if !matches {
+        return Err(OAuthError::invalid_client("Incorrect client credentials."));
+    }
+    return Ok(client);
+}
+
+/// Authenticates clients that have a secret, public clients pass as they are.
+pub fn authenticate_if_confidential(db_conn: &my::Pool, client_id: &str, client_secret: &Option<String>) -> Result<Client, OAuthError> {
+    let found = client(db_conn, client_id)?;
+    if found.client_secret_hash.is_none() {
+        return Ok(found);
+    }
+    return authenticate_client(db_conn, client_id, client_secret.as_ref().map_or("", |secret| secret.as_str()));
+}
+
+fn required<'a>(value: &'a Option<String>, name: &str) -> Result<&'a str, OAuthError> {
+    match value {
+        Some(value) => Ok(value.as_str()),
+        None => Err(OAuthError::invalid_request(&format!("Missing {}.", name)))
+    }
+}
+
+/// Tokens of a user for `client_id`, with an ID token when `scope` includes `openid`.
+/// `user.sub` is `users.subject`, both tokens get the client's `sub` for it.
+#[allow(clippy::too_many_arguments)]
+fn user_tokens(
+    config: &crate::Config,
+    transaction: &mut my::Transaction,
+    user_id: usize,
+    user: UserInfo,
+    client_id: &str,
+    scope: &str,
+    nonce: Option<String>,
+    auth_time: i64
+) -> Result<Tokens, OAuthError> {
+    let refresh_token = refresh_token::issue(config, transaction, user_id, &generate_random(32), client_id)?;
+    let id_token = if scope.split_whitespace().any(|scope| scope == "openid") {
+        Some(id_token(config, user.clone(), client_id, nonce, auth_time)?)
+    } else {
+        None
+    };
+    let access_token = access_token(config, &user, client_id)?;
+    return Ok(tokens(config, access_token, Some(refresh_token), id_token));
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |difference, (x, y)| difference | (x ^ y)) == 0
+}
+
+/// base64url of the SHA-256 of `code_verifier`, https://tools.ietf.org/html/rfc7636#section-4.6
+fn code_challenge(code_verifier: &str) -> String {
+    return base64::encode_config(&Sha256::digest(code_verifier.as_bytes()), base64::URL_SAFE_NO_PAD);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn s256_code_challenge() {
+        // https://tools.ietf.org/html/rfc7636#appendix-B
+        assert_eq!(
+            code_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn secret_comparison() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"abcd"));
+    }
+
+    #[test]
+    fn validation_error() {
+        let e = OAuthError::from(AuthorizeDTO { response_type: "code".to_string(), ..Default::default() }.validate().unwrap_err());
+
+        assert_eq!(e.error, "invalid_request");
+        assert_eq!(e.error_description, "Invalid client_id, code_challenge, code_challenge_method, redirect_uri.");
+    }
+}
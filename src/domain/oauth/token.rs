@@ -0,0 +1,184 @@
+use validator::{Validate};
+use mysql as my;
+use crate::domain::user::{self, client_access_token, hash_token, tokens, RefreshTokenDTO, Tokens, UserInfo, DTOErrors};
+use crate::domain::oauth::{
+    authenticate_client, authenticate_if_confidential, code_challenge, device, required, user_tokens, OAuthError, TokenDTO
+};
+
+#[derive(Debug, PartialEq)]
+enum Grant {
+    AuthorizationCode,
+    RefreshToken,
+    ClientCredentials,
+    DeviceCode
+}
+
+fn grant(grant_type: &str) -> Result<Grant, OAuthError> {
+    match grant_type {
+        "authorization_code" => Ok(Grant::AuthorizationCode),
+        "refresh_token" => Ok(Grant::RefreshToken),
+        "client_credentials" => Ok(Grant::ClientCredentials),
+        device::GRANT_TYPE => Ok(Grant::DeviceCode),
+        _ => Err(OAuthError::new(
+            "unsupported_grant_type",
+            &format!("Supported grant types are authorization_code, refresh_token, client_credentials and {}.", device::GRANT_TYPE)
+        ))
+    }
+}
+
+/// `code`, `redirect_uri`, `client_id` and `code_verifier`.
+fn code_params(data: &TokenDTO) -> Result<(&str, &str, &str, &str), OAuthError> {
+    return Ok((
+        required(&data.code, "code")?,
+        required(&data.redirect_uri, "redirect_uri")?,
+        required(&data.client_id, "client_id")?,
+        required(&data.code_verifier, "code_verifier")?
+    ));
+}
+
+/// https://tools.ietf.org/html/rfc6749#section-4.1.3
+fn authorization_code(config: &crate::Config, db_conn: &my::Pool, data: &TokenDTO) -> Result<Tokens, OAuthError> {
+    let (code, redirect_uri, client_id, code_verifier) = code_params(data)?;
+
+    // Public clients rely on PKCE alone.
+    authenticate_if_confidential(db_conn, client_id, &data.client_secret)?;
+
+    let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false))
+        .map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    let result: Vec<(usize, String, usize, String, String, String, Option<String>, i64, String, String, String, bool)> = transaction.prep_exec(r"
+        SELECT ac.id, c.client_id, ac.user_id, ac.redirect_uri, ac.code_challenge, ac.scope, ac.nonce, ac.auth_time,
+            u.subject, u.username, u.email, u.email_verified
+        FROM oauth_authorization_codes ac
+        INNER JOIN oauth_clients c ON c.id = ac.client_id
+        INNER JOIN users u ON u.id = ac.user_id
+        WHERE ac.code_hash = :code_hash AND ac.expires_at > NOW() AND u.enabled = 1
+        FOR UPDATE", params!{
+            "code_hash" => hash_token(code)
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (id, client_id, user_id, redirect_uri, code_challenge, scope, nonce, auth_time, subject, username, email, email_verified) = my::from_row(row);
+                (id, client_id, user_id, redirect_uri, code_challenge, scope, nonce, auth_time, subject, username, email, email_verified)
+            }).collect()
+        }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    if result.is_empty() {
+        return Err(OAuthError::invalid_grant("Incorrect or expired code."));
+    }
+
+    let (id, code_client_id, user_id, code_redirect_uri, challenge, scope, nonce, auth_time, subject, username, email, email_verified) = result[0].clone();
+
+    // Codes can only be exchanged once, whether or not this exchange succeeds.
+    transaction.prep_exec(r"DELETE FROM oauth_authorization_codes WHERE id = :id", params!{
+        "id" => &id
+    }).map_err(|e| OAuthError::server_error(&e.to_string()))?;
+
+    let exchanged = if code_client_id != client_id {
+        Err(OAuthError::invalid_grant("The code was issued to another client."))
+    } else if code_redirect_uri != redirect_uri {
+        Err(OAuthError::invalid_grant("redirect_uri doesn't match the authorization request."))
+    } else if code_challenge(code_verifier) != challenge {
+        Err(OAuthError::invalid_grant("Incorrect code_verifier."))
+    } else {
+        Ok(())
+    };
+
+    let tokens = match exchanged {
+        Ok(_) => {
+            let user = UserInfo { sub: subject, preferred_username: username, email, email_verified };
+            user_tokens(config, &mut transaction, user_id, user, client_id, &scope, nonce, auth_time)
+        },
+        Err(e) => Err(e)
+    };
+
+    match transaction.commit() {
+        Ok(_) => return tokens,
+        Err(e) => return Err(OAuthError::server_error(&e.to_string()))
+    }
+}
+
+/// https://tools.ietf.org/html/rfc6749#section-6
+fn refresh(config: &crate::Config, db_conn: &my::Pool, data: &TokenDTO) -> Result<Tokens, OAuthError> {
+    let api_param = RefreshTokenDTO { refresh_token: required(&data.refresh_token, "refresh_token")?.to_string() };
+
+    match user::refresh_token::run(config, db_conn, &api_param) {
+        Ok(tokens) => return Ok(tokens),
+        Err(DTOErrors::ApplicationError(e)) => return Err(OAuthError::invalid_grant(&e)),
+        Err(e) => return Err(OAuthError::from(e))
+    }
+}
+
+/// `client_id` and `client_secret`, only confidential clients may use the grant.
+fn client_credentials_params(data: &TokenDTO) -> Result<(&str, &str), OAuthError> {
+    let client_id = required(&data.client_id, "client_id")?;
+    match data.client_secret {
+        Some(ref client_secret) => return Ok((client_id, client_secret.as_str())),
+        None => return Err(OAuthError::invalid_client("Missing client_secret."))
+    }
+}
+
+/// https://tools.ietf.org/html/rfc6749#section-4.4
+fn client_credentials(config: &crate::Config, db_conn: &my::Pool, data: &TokenDTO) -> Result<Tokens, OAuthError> {
+    let (client_id, client_secret) = client_credentials_params(data)?;
+    let client = authenticate_client(db_conn, client_id, client_secret)?;
+
+    // Without a `scope` the client gets every scope it may request.
+    let scope: Vec<String> = match data.scope {
+        Some(ref scope) => scope.split_whitespace().map(|scope| scope.to_string()).collect(),
+        None => client.scopes.clone()
+    };
+    if let Some(scope) = scope.iter().find(|scope| !client.scopes.contains(scope)) {
+        return Err(OAuthError::new("invalid_scope", &format!("The client may not request {}.", scope)));
+    }
+
+    let access_token = client_access_token(config, client.client_id, scope.join(" "))?;
+    return Ok(tokens(config, access_token, None, None));
+}
+
+/// Tokens for the grant in `data`.
+pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &TokenDTO) -> Result<Tokens, OAuthError> {
+    data.validate()?;
+    match grant(&data.grant_type)? {
+        Grant::AuthorizationCode => authorization_code(config, db_conn, data),
+        Grant::RefreshToken => refresh(config, db_conn, data),
+        Grant::ClientCredentials => client_credentials(config, db_conn, data),
+        Grant::DeviceCode => device::poll(config, db_conn, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grants() {
+        assert_eq!(grant("authorization_code"), Ok(Grant::AuthorizationCode));
+        assert_eq!(grant("refresh_token"), Ok(Grant::RefreshToken));
+        assert_eq!(grant("client_credentials"), Ok(Grant::ClientCredentials));
+        assert_eq!(grant(device::GRANT_TYPE), Ok(Grant::DeviceCode));
+    }
+
+    #[test]
+    fn unsupported_grant_type() {
+        assert_eq!(grant("password").unwrap_err().error, "unsupported_grant_type");
+    }
+
+    #[test]
+    fn missing_code() {
+        let data = TokenDTO { grant_type: "authorization_code".to_string(), ..Default::default() };
+
+        assert_eq!(code_params(&data).unwrap_err(), OAuthError::invalid_request("Missing code."));
+    }
+
+    #[test]
+    fn missing_client_secret() {
+        let data = TokenDTO {
+            grant_type: "client_credentials".to_string(),
+            client_id: Some("mailer".to_string()),
+            ..Default::default()
+        };
+
+        assert_eq!(client_credentials_params(&data).unwrap_err(), OAuthError::invalid_client("Missing client_secret."));
+    }
+}
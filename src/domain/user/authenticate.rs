@@ -1,19 +1,81 @@
-use jwt::{decode, Validation};
-use crate::domain::user::{Claims, AuthenticateDTO, DTOErrors};
+use validator::{Validate};
+use mysql as my;
+use chrono::{Utc};
+use jwt::{decode, decode_header, Validation};
+use crate::domain::user::revocation::Revocations;
+use crate::domain::user::{userinfo, Claims, AuthenticateDTO, Identity, Principal, DTOErrors};
 
-pub fn run(config: &crate::Config, data: &AuthenticateDTO) -> Result<bool, DTOErrors> {
-    let mut validation = Validation { iss: Some(config.domain.to_string()), sub: Some(config.subject.to_string()), ..Default::default()};
-    validation.set_audience(&config.app_name.to_string());
-    let token_verification = decode::<Claims>(&data.token, config.secret.as_ref(), &validation);
+/// Verifies `token`, checks it hasn't been revoked and returns its claims.
+pub fn claims(config: &crate::Config, db_conn: &my::Pool, revocations: &Revocations, token: &str) -> Result<Claims, DTOErrors> {
+    let incorrect = || DTOErrors::ApplicationError("Incorrect token.".to_string());
+    let header = decode_header(token).map_err(|_| incorrect())?;
+    let (key, algorithm) = config.keys.decoding_key(&header).ok_or_else(incorrect)?;
+
+    let mut validation = Validation::new(algorithm);
+    validation.set_issuer(&[&config.domain]);
+    validation.set_audience(&[&config.app_name]);
+    let token_verification = decode::<Claims>(token, key, &validation);
     match token_verification {
-        Ok(_) => return Ok(true),
-        Err(_) => return Err(DTOErrors::ApplicationError("Incorrect token.".to_string()))
+        Ok(token_data) => {
+            if token_data.claims.principal().is_none() {
+                return Err(incorrect());
+            }
+            if revocations.is_revoked(config, db_conn, &token_data.claims)? {
+                return Err(DTOErrors::ApplicationError("Revoked token.".to_string()));
+            }
+            return Ok(token_data.claims)
+        },
+        Err(_) => return Err(incorrect())
+    };
+}
+
+/// Id of the enabled user a token belongs to, clients only need to still be registered.
+fn verify(db_conn: &my::Pool, principal: &Principal) -> Result<Option<usize>, DTOErrors> {
+    match principal {
+        Principal::User { username, .. } => return userinfo::user(db_conn, username).map(|(user_id, _)| Some(user_id)),
+        Principal::Client { client_id, .. } => {
+            let result: Vec<usize> = db_conn.prep_exec(r"SELECT id FROM oauth_clients WHERE client_id = :client_id", params!{
+                "client_id" => client_id
+            }).map(|result| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                result.map(|x| x.unwrap()).map(my::from_row).collect()
+            }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+            if result.is_empty() {
+                return Err(DTOErrors::ApplicationError("Client not found.".to_string()));
+            }
+            return Ok(None);
+        }
+    }
+}
+
+fn identity(claims: &Claims, id: Option<usize>, now: i64) -> Identity {
+    Identity {
+        sub: claims.sub.clone(),
+        principal: claims.principal().unwrap(),
+        id,
+        scopes: claims.scope.as_ref().map_or(vec![], |scope| scope.split_whitespace().map(|scope| scope.to_string()).collect()),
+        exp: claims.exp,
+        expires_in: (claims.exp - now).max(0)
+    }
+}
+
+/// Who the token belongs to, whether a user or an OAuth client.
+pub fn run(config: &crate::Config, db_conn: &my::Pool, revocations: &Revocations, data: &AuthenticateDTO) -> Result<Identity, DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let claims = claims(config, db_conn, revocations, &data.token)?;
+            let principal = claims.principal().unwrap();
+            let id = if data.verify_user.unwrap_or(false) { verify(db_conn, &principal)? } else { None };
+            return Ok(identity(&claims, id, Utc::now().timestamp()));
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
     };
 }
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
     // use std::collections::HashMap;
     // use serde_json::Value as JsonValue;
     // use serde_json::Number as Number;
@@ -22,6 +84,27 @@ mod tests {
     #[test]
     fn invalid_token() {}
 
+    #[test]
+    fn client_identity() {
+        let claims = Claims {
+            exp: 1900,
+            iat: 1000,
+            client_id: Some("worker".to_string()),
+            scope: Some("mail:send mail:read".to_string()),
+            ..crate::domain::user::claims()
+        };
+
+        assert_eq!(serde_json::to_value(identity(&claims, None, 1300)).unwrap(), json!({
+            "sub": "authentication",
+            "type": "client",
+            "client_id": "worker",
+            "scope": "mail:send mail:read",
+            "scopes": ["mail:send", "mail:read"],
+            "exp": 1900,
+            "expires_in": 600
+        }));
+    }
+
     #[test]
     fn success() {}
 }
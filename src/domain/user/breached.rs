@@ -0,0 +1,243 @@
+//! Passwords known from breaches, checked offline.
+//!
+//! `BREACHED_PASSWORDS` points at either a Have I Been Pwned style list, one
+//! uppercase hex SHA-1 per line optionally followed by `:count`, or a Bloom
+//! filter built from such a list with
+//!
+//! ```text
+//! auto-chat-api build-breach-filter pwned-passwords-sha1.txt breached.bloom [false_positive_rate]
+//! ```
+//!
+//! The full list doesn't fit in memory, the filter takes about 1.8 GB per
+//! billion passwords at the default 0.1% false positive rate. False positives
+//! only ever reject a password that wasn't actually breached.
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+
+const BLOOM_MAGIC: &[u8; 8] = b"BREACHBF";
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.001;
+
+type Sha1Hash = [u8; 20];
+
+pub struct BloomFilter {
+    hashes: u32,
+    bits: u64,
+    words: Vec<u64>
+}
+
+impl BloomFilter {
+    fn new(items: u64, false_positive_rate: f64) -> BloomFilter {
+        let items = items.max(1) as f64;
+        let bits = (-items * false_positive_rate.ln() / (2f64.ln() * 2f64.ln())).ceil().max(64.0) as u64;
+        let hashes = ((bits as f64 / items) * 2f64.ln()).round().max(1.0) as u32;
+        BloomFilter { hashes, bits, words: vec![0; ((bits + 63) / 64) as usize] }
+    }
+
+    /// SHA-1 is already uniform, so its first 16 bytes make the two hashes of
+    /// the Kirsch-Mitzenmacher scheme.
+    fn positions<'a>(&'a self, hash: &Sha1Hash) -> impl Iterator<Item = u64> + 'a {
+        let mut h1 = [0u8; 8];
+        let mut h2 = [0u8; 8];
+        h1.copy_from_slice(&hash[..8]);
+        h2.copy_from_slice(&hash[8..16]);
+        let (h1, h2) = (u64::from_le_bytes(h1), u64::from_le_bytes(h2));
+        (0..u64::from(self.hashes)).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.bits)
+    }
+
+    fn insert(&mut self, hash: &Sha1Hash) {
+        let positions: Vec<u64> = self.positions(hash).collect();
+        for position in positions {
+            self.words[(position / 64) as usize] |= 1 << (position % 64);
+        }
+    }
+
+    fn contains(&self, hash: &Sha1Hash) -> bool {
+        self.positions(hash).all(|position| self.words[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(BLOOM_MAGIC)?;
+        writer.write_all(&self.hashes.to_le_bytes())?;
+        writer.write_all(&self.bits.to_le_bytes())?;
+        for word in &self.words {
+            writer.write_all(&word.to_le_bytes())?;
+        }
+        return writer.flush();
+    }
+
+    /// `len` is the length of what follows the magic, checked against the
+    /// header before anything is allocated.
+    fn read<R: Read>(reader: &mut R, len: u64) -> io::Result<BloomFilter> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut hashes = [0u8; 4];
+        let mut bits = [0u8; 8];
+        reader.read_exact(&mut hashes)?;
+        reader.read_exact(&mut bits)?;
+        let (hashes, bits) = (u32::from_le_bytes(hashes), u64::from_le_bytes(bits));
+        if hashes == 0 || bits == 0 {
+            return Err(invalid("Bloom filter without hashes or bits."));
+        }
+        let words = (bits - 1) / 64 + 1;
+        if words.checked_mul(8).and_then(|bytes| bytes.checked_add(12)) != Some(len) {
+            return Err(invalid("Bloom filter length doesn't match its header."));
+        }
+
+        let mut words = vec![0u64; words as usize];
+        let mut word = [0u8; 8];
+        for slot in words.iter_mut() {
+            reader.read_exact(&mut word)?;
+            *slot = u64::from_le_bytes(word);
+        }
+        return Ok(BloomFilter { hashes, bits, words });
+    }
+}
+
+pub enum BreachList {
+    None,
+    Hashes(HashSet<Sha1Hash>),
+    Bloom(BloomFilter)
+}
+
+impl std::fmt::Debug for BreachList {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            BreachList::None => write!(f, "BreachList::None"),
+            BreachList::Hashes(hashes) => write!(f, "BreachList::Hashes({} hashes)", hashes.len()),
+            BreachList::Bloom(bloom) => write!(f, "BreachList::Bloom({} bits, {} hashes)", bloom.bits, bloom.hashes)
+        }
+    }
+}
+
+impl Default for BreachList {
+    fn default() -> BreachList {
+        BreachList::None
+    }
+}
+
+/// The SHA-1 of a `SHA1HEX[:count]` line.
+fn parse_line(line: &str) -> Option<Sha1Hash> {
+    let hex = line.trim().split(':').next()?;
+    if hex.len() != 40 {
+        return None;
+    }
+    let mut hash = [0u8; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    return Some(hash);
+}
+
+fn sha1(password: &str) -> Sha1Hash {
+    let mut hash = [0u8; 20];
+    hash.copy_from_slice(&Sha1::digest(password.as_bytes()));
+    return hash;
+}
+
+impl BreachList {
+    /// A Bloom filter when the file starts like one, a list of hashes otherwise.
+    pub fn load(path: &str) -> io::Result<BreachList> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let mut reader = BufReader::new(file);
+        if reader.fill_buf()?.starts_with(BLOOM_MAGIC) {
+            reader.consume(BLOOM_MAGIC.len());
+            return Ok(BreachList::Bloom(BloomFilter::read(&mut reader, len - BLOOM_MAGIC.len() as u64)?));
+        }
+
+        let mut hashes = HashSet::new();
+        for line in reader.lines() {
+            if let Some(hash) = parse_line(&line?) {
+                hashes.insert(hash);
+            }
+        }
+        return Ok(BreachList::Hashes(hashes));
+    }
+
+    pub fn contains(&self, password: &str) -> bool {
+        match self {
+            BreachList::None => false,
+            BreachList::Hashes(hashes) => hashes.contains(&sha1(password)),
+            BreachList::Bloom(bloom) => bloom.contains(&sha1(password))
+        }
+    }
+}
+
+/// `build-breach-filter`, reads the list twice to size the filter without holding it in memory.
+pub fn build_filter(input: &str, output: &str, false_positive_rate: f64) -> io::Result<u64> {
+    if false_positive_rate.is_nan() || false_positive_rate <= 0.0 || false_positive_rate >= 1.0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "false_positive_rate needs to be between 0 and 1."));
+    }
+
+    let lines = || -> io::Result<_> {
+        Ok(BufReader::new(File::open(input)?).lines().filter_map(|line| parse_line(&line.ok()?)))
+    };
+
+    let items = lines()?.count() as u64;
+    let mut bloom = BloomFilter::new(items, false_positive_rate);
+    for hash in lines()? {
+        bloom.insert(&hash);
+    }
+
+    bloom.write(&mut BufWriter::new(File::create(output)?))?;
+    return Ok(items);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SHA-1 of "password"
+    const PASSWORD: &str = "5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8";
+
+    #[test]
+    fn parsed_line() {
+        assert_eq!(parse_line(&format!("{}:3861493", PASSWORD)), Some(sha1("password")));
+        assert_eq!(parse_line(PASSWORD), Some(sha1("password")));
+        assert_eq!(parse_line("not a hash"), None);
+    }
+
+    #[test]
+    fn hashes() {
+        let list = BreachList::Hashes(vec![sha1("password")].into_iter().collect());
+
+        assert!(list.contains("password"));
+        assert!(!list.contains("correct horse battery staple"));
+        assert!(!BreachList::None.contains("password"));
+    }
+
+    #[test]
+    fn bloom_filter_round_trip() {
+        let mut bloom = BloomFilter::new(1000, DEFAULT_FALSE_POSITIVE_RATE);
+        for i in 0..1000 {
+            bloom.insert(&sha1(&format!("password{}", i)));
+        }
+        let mut bytes = vec![];
+        bloom.write(&mut bytes).unwrap();
+        let payload = &bytes[BLOOM_MAGIC.len()..];
+        let list = BreachList::Bloom(BloomFilter::read(&mut &payload[..], payload.len() as u64).unwrap());
+
+        assert!((0..1000).all(|i| list.contains(&format!("password{}", i))));
+        assert!((0..1000).filter(|i| list.contains(&format!("correct horse {}", i))).count() < 10);
+    }
+
+    #[test]
+    fn invalid_bloom_filter() {
+        let header = |hashes: u32, bits: u64| [&hashes.to_le_bytes()[..], &bits.to_le_bytes()[..]].concat();
+        let valid = [header(7, 64), vec![0; 8]].concat();
+
+        assert!(BloomFilter::read(&mut &valid[..], 20).is_ok());
+        assert!(BloomFilter::read(&mut &[header(0, 64), vec![0; 8]].concat()[..], 20).is_err());
+        assert!(BloomFilter::read(&mut &header(7, 0)[..], 12).is_err());
+        assert!(BloomFilter::read(&mut &valid[..], 28).is_err());
+        assert!(BloomFilter::read(&mut &header(7, u64::max_value())[..], 12).is_err());
+    }
+
+    #[test]
+    fn false_positive_rate() {
+        assert_eq!(build_filter("missing.txt", "missing.bloom", 0.0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(build_filter("missing.txt", "missing.bloom", 1.0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(build_filter("missing.txt", "missing.bloom", std::f64::NAN).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,91 @@
+use validator::{Validate};
+use mysql as my;
+use crate::domain::user::{
+    hash_token, new_password_hash, password_history, send_email, sign_in, ChangePasswordDTO, Claims, SignInDTO, DTOErrors
+};
+
+/// Changes the password of the user `claims` belong to, who has to know the current one.
+/// Other sessions are signed out by revoking every refresh token family but
+/// the one of `data.refresh_token`. Their access tokens are left to expire,
+/// revoking them by `iat` like `sign_out_everywhere` would sign the caller out too.
+pub fn run(config: &crate::Config, db_conn: &my::Pool, claims: &Claims, data: &ChangePasswordDTO) -> Result<bool, DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let current = SignInDTO {
+                username_or_email: claims.username()?.to_string(),
+                password: data.current_password.to_owned(),
+                nonce: None
+            };
+            let (user_id, user) = sign_in::credentials(config, db_conn, &current)?;
+
+            let hashed = new_password_hash(config, db_conn, user_id, &user.preferred_username, &user.email, &data.password)?;
+
+            let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false))
+                .map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+            password_history::record(config, &mut transaction, user_id, &hashed)?;
+
+            transaction.prep_exec(r"UPDATE users SET password = :password, salt = NULL WHERE id = :user_id", params!{
+                "password" => &hashed,
+                "user_id" => &user_id
+            }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+            // MySQL doesn't take the updated table in a subquery, only in a derived table.
+            transaction.prep_exec(r"
+                UPDATE refresh_tokens SET revoked = 1
+                WHERE user_id = :user_id AND family NOT IN (
+                    SELECT family FROM (
+                        SELECT family FROM refresh_tokens WHERE user_id = :user_id AND token_hash = :token_hash
+                    ) kept
+                )", params!{
+                    "user_id" => &user_id,
+                    "token_hash" => data.refresh_token.as_ref().map(|refresh_token| hash_token(refresh_token))
+                }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+            match transaction.commit() {
+                Ok(_) => {
+                    // @TODO: use Futures not need to await
+                    let message = "Your password has been updated.";
+                    send_email(config, &user.email, &user.preferred_username, message);
+
+                    return Ok(true)
+                },
+                Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+            }
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::user::password;
+
+    #[test]
+    fn invalid_dto() {
+        let data = ChangePasswordDTO {
+            current_password: "".to_string(),
+            password: "correct horse battery staple".to_string(),
+            refresh_token: Some("".to_string())
+        };
+        let e = data.validate().unwrap_err();
+
+        assert!(e.field_errors().contains_key("current_password"));
+        assert!(e.field_errors().contains_key("refresh_token"));
+    }
+
+    // `run` checks the current password through `sign_in::credentials`, i.e.
+    // `check_password`, before the new one is hashed.
+    #[test]
+    fn incorrect_current_password() {
+        let params = password::HashParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let hashed = password::hash(&params, "correct horse battery staple").unwrap();
+
+        assert_eq!(
+            sign_in::check_password("incorrect", &hashed, None),
+            Err(DTOErrors::ApplicationError("Incorrect password.".to_string()))
+        );
+        assert_eq!(sign_in::check_password("correct horse battery staple", &hashed, None), Ok(()));
+    }
+}
@@ -0,0 +1,51 @@
+use validator::{Validate, ValidationErrors};
+use crate::domain::user::password_policy::{PasswordPolicy, Strength};
+use crate::domain::user::{CheckPasswordStrengthDTO, DTOErrors};
+
+/// How strong a password is and which rules of the policy it breaks, so that
+/// clients can give feedback before signing up or updating a password.
+pub fn run(policy: &PasswordPolicy, data: &CheckPasswordStrengthDTO) -> Result<(Strength, ValidationErrors), DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let user_inputs: Vec<&str> = data.username.iter().chain(data.email.iter()).map(|input| input.as_str()).collect();
+            return Ok(policy.evaluate(&data.password, &user_inputs));
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dto(password: &str) -> CheckPasswordStrengthDTO {
+        CheckPasswordStrengthDTO {
+            password: password.to_string(),
+            username: Some("farhan".to_string()),
+            email: Some("farhan@example.com".to_string())
+        }
+    }
+
+    #[test]
+    fn invalid_dto() {
+        assert!(run(&PasswordPolicy::default(), &dto("")).is_err());
+        assert!(run(&PasswordPolicy::default(), &dto(&"a".repeat(1025))).is_err());
+    }
+
+    #[test]
+    fn weak_password() {
+        let (strength, errors) = run(&PasswordPolicy::default(), &dto("farhan2019")).unwrap();
+        let codes: Vec<String> = errors.field_errors()["password"].iter().map(|e| e.code.to_string()).collect();
+
+        assert!(strength.score < 2);
+        assert_eq!(codes, vec!["contains_identity", "too_weak"]);
+    }
+
+    #[test]
+    fn success() {
+        let (strength, errors) = run(&PasswordPolicy::default(), &dto("correct horse battery staple")).unwrap();
+
+        assert!(strength.score >= 3);
+        assert!(errors.is_empty());
+    }
+}
@@ -4,11 +4,26 @@ pub mod sign_up;
 pub mod sign_in;
 pub mod sign_up_without_password;
 pub mod identity_check;
+pub mod keys;
 pub mod forgot_my_password;
+pub mod refresh_token;
+pub mod revocation;
+pub mod sign_out;
+pub mod sign_out_everywhere;
+pub mod userinfo;
+pub mod password;
+pub mod password_policy;
+pub mod breached;
+pub mod password_history;
+pub mod change_password;
+pub mod check_password_strength;
 
 use validator::{Validate, ValidationErrors};
+use mysql as my;
 use serde::ser::{Serialize, Serializer};
-use bcrypt::{verify};
+use jwt::{encode};
+use chrono::{Utc};
+use sha2::{Digest, Sha256};
 use rand::Rng;
 use rand::thread_rng;
 use rand::distributions::Alphanumeric;
@@ -22,17 +37,139 @@ use lettre::smtp::ConnectionReuseParameters;
 
 /// Our claims struct, it needs to derive `Serialize` and/or `Deserialize`
 /// https://tools.ietf.org/html/rfc7519#section-4.1
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub iss: String, // domain name
+    pub aud: String, // this service name i.e. user-service OR the application name that will be using this JWT. THe client must verify this string, if not the same then reject token
+    pub sub: String, // `users.subject` of users, see `SubjectType`, `client_id` of clients
+    pub exp: i64,
+    pub iat: i64,
+    pub jti: String, // unique per token, see `revocation`
+    // Set on tokens of users
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+    // Set on tokens of OAuth clients, issued by the client_credentials grant
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scope: Option<String>
+}
+
+/// Who a token was issued to.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Principal {
+    User { username: String, email: String },
+    Client { client_id: String, scope: String }
+}
+
+impl Claims {
+    /// `None` for tokens that are neither a user's nor a client's.
+    pub fn principal(&self) -> Option<Principal> {
+        match (&self.username, &self.email, &self.client_id) {
+            (Some(username), Some(email), None) => Some(Principal::User { username: username.clone(), email: email.clone() }),
+            (None, None, Some(client_id)) => Some(Principal::Client {
+                client_id: client_id.clone(),
+                scope: self.scope.clone().unwrap_or_default()
+            }),
+            _ => None
+        }
+    }
+
+    /// Username of a user's token, fails for client tokens.
+    pub fn username(&self) -> Result<&str, DTOErrors> {
+        match self.username {
+            Some(ref username) => Ok(username.as_str()),
+            None => Err(DTOErrors::ApplicationError("Not a user token.".to_string()))
+        }
+    }
+}
+
+/// What a verified token says about its bearer, returned by `authenticate::run`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Identity {
+    pub sub: String,
+    #[serde(flatten)]
+    pub principal: Principal,
+    /// Id of the user, only looked up with `verify_user`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<usize>,
+    pub scopes: Vec<String>,
+    pub exp: i64,
+    /// Seconds until `exp`.
+    pub expires_in: i64
+}
+
+/// How `sub` is derived from `users.subject`, https://openid.net/specs/openid-connect-core-1_0.html#SubjectIDTypes
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubjectType {
+    /// Every client sees the same `sub`.
+    Public,
+    /// Every client sees a different `sub`, so clients can't correlate users
+    /// with each other. `salt` keeps clients from computing each other's.
+    Pairwise { salt: String }
+}
+
+impl SubjectType {
+    pub fn name(&self) -> &'static str {
+        match self {
+            SubjectType::Public => "public",
+            SubjectType::Pairwise { .. } => "pairwise"
+        }
+    }
+
+    /// `sub` of the user with `subject` as seen by `audience`, a client_id or APP_NAME.
+    pub fn subject(&self, subject: &str, audience: &str) -> String {
+        match self {
+            SubjectType::Public => subject.to_string(),
+            SubjectType::Pairwise { salt } => base64::encode_config(
+                &Sha256::digest(format!("{}:{}:{}", audience, subject, salt).as_bytes()),
+                base64::URL_SAFE_NO_PAD
+            )
+        }
+    }
+}
+
+/// Standard claims of a user, https://openid.net/specs/openid-connect-core-1_0.html#StandardClaims
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UserInfo {
+    pub sub: String,
+    pub preferred_username: String,
+    pub email: String,
+    pub email_verified: bool
+}
+
+/// https://openid.net/specs/openid-connect-core-1_0.html#IDToken
 #[derive(Debug, Serialize, Deserialize)]
-struct Claims {
-    iss: String, // domain name
-    aud: String, // this service name i.e. user-service OR the application name that will be using this JWT. THe client must verify this string, if not the same then reject token
-    sub: String, // subject
-    exp: i64,
-    username: String,
-    email : String
+pub struct IdTokenClaims {
+    pub iss: String, // ISSUER_URL
+    pub aud: String,
+    pub exp: i64,
+    pub iat: i64,
+    pub auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    #[serde(flatten)]
+    pub user: UserInfo
+}
+
+/// Returned by `sign_in::run` and `refresh_token::run`.
+#[derive(Debug, Serialize)]
+pub struct Tokens {
+    pub access_token: String,
+    /// Not issued to OAuth clients for the client_credentials grant.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+    pub token_type: String,
+    pub expires_in: i64,
+    /// Only issued when the user authenticates, not when tokens are refreshed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id_token: Option<String>
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct SignUpWithoutPasswordDTO {
     #[validate(length(min = 2))]
     pub username: String,
@@ -41,7 +178,7 @@ pub struct SignUpWithoutPasswordDTO {
     pub email: String
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct SignUpDTO {
     #[validate(length(min = 2))]
     pub username: String,
@@ -49,46 +186,94 @@ pub struct SignUpDTO {
     #[validate(email)]
     pub email: String,
 
-    #[validate(length(min = 6))]
+    #[validate(length(min = 6, max = 1024))]
     pub password: String
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct SignInDTO {  
     #[validate(length(min = 1))]
     pub username_or_email: String,
 
-    #[validate(length(min = 6))]
-    pub password: String
+    #[validate(length(min = 6, max = 1024))]
+    pub password: String,
+
+    /// Echoed in the ID token.
+    #[validate(length(min = 1))]
+    pub nonce: Option<String>
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct AuthenticateDTO {  
     #[validate(length(min = 1))]
     pub token: String,
+
+    /// Also checks that the user is still enabled, or that the client still exists.
+    #[serde(default)]
+    pub verify_user: Option<bool>
+}
+
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+pub struct CheckPasswordStrengthDTO {
+    #[validate(length(min = 1, max = 1024))]
+    pub password: String,
+
+    /// Of the user the password is for, passwords containing them are rejected.
+    #[serde(default)]
+    pub username: Option<String>,
+
+    #[serde(default)]
+    pub email: Option<String>
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct UpdatePasswordDTO {  
     #[validate(length(min = 1))]
     pub token: String,
 
-    #[validate(length(min = 6))]
+    #[validate(length(min = 6, max = 1024))]
     pub password: String
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct IdentityCheckDTO {  
     #[validate(length(min = 1))]
     pub identity: String
 }
 
-#[derive(Debug, Validate, Serialize, Deserialize)]
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
 pub struct ForgotMyPasswordDTO {
     #[validate(length(min = 1))]
     pub username_or_email: String
 }
 
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+pub struct RefreshTokenDTO {
+    #[validate(length(min = 1))]
+    pub refresh_token: String
+}
+
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+pub struct ChangePasswordDTO {
+    #[validate(length(min = 1, max = 1024))]
+    pub current_password: String,
+
+    #[validate(length(min = 6, max = 1024))]
+    pub password: String,
+
+    /// Refresh token of the caller's session, the only one that stays valid.
+    #[serde(default)]
+    #[validate(length(min = 1))]
+    pub refresh_token: Option<String>
+}
+
+#[derive(Debug, Default, Validate, Serialize, Deserialize)]
+pub struct SignOutDTO {
+    /// Also revokes this refresh token and the ones rotated from the same sign in.
+    #[validate(length(min = 1))]
+    pub refresh_token: Option<String>
+}
+
 #[derive(PartialEq, Debug)]
 pub enum DTOErrors {
     ValidationError(ValidationErrors),
@@ -109,13 +294,114 @@ impl Serialize for DTOErrors {
     }
 }
 
-fn generate_random(count: usize) -> String {
+pub(crate) fn generate_random(count: usize) -> String {
     return thread_rng()
         .sample_iter(&Alphanumeric)
         .take(count)
         .collect();
 }
 
+/// SHA-256 of a random token, used to store tokens that are looked up by value.
+pub(crate) fn hash_token(token: &str) -> String {
+    return format!("{:x}", Sha256::digest(token.as_bytes()));
+}
+
+/// Signs an access token valid for `ACCESS_TOKEN_EXPIRY` minutes, `set` fills in who it is for.
+fn sign_access_token<F: FnOnce(&mut Claims)>(config: &crate::Config, set: F) -> Result<String, DTOErrors> {
+    let dt = Utc::now();
+    let mut my_claims = Claims {
+        iss: config.domain.to_string(),
+        aud: config.app_name.to_string(),
+        sub: String::new(),
+        exp: dt.timestamp() + 60 * i64::from(config.access_token_exp),
+        iat: dt.timestamp(),
+        jti: generate_random(32),
+        username: None,
+        email: None,
+        client_id: None,
+        scope: None
+    };
+    set(&mut my_claims);
+
+    return match encode(&config.keys.header(), &my_claims, config.keys.encoding_key()) {
+        Ok(token) => Ok(token),
+        Err(e) => Err(DTOErrors::ApplicationError(e.to_string()))
+    };
+}
+
+/// Access token of `user`, whose `sub` is `users.subject`, for `audience`.
+pub(crate) fn access_token(config: &crate::Config, user: &UserInfo, audience: &str) -> Result<String, DTOErrors> {
+    return sign_access_token(config, |my_claims| {
+        my_claims.sub = config.subject_type.subject(&user.sub, audience);
+        my_claims.username = Some(user.preferred_username.clone());
+        my_claims.email = Some(user.email.clone());
+    });
+}
+
+/// Access token of an OAuth client, `scope` is space separated.
+pub(crate) fn client_access_token(config: &crate::Config, client_id: String, scope: String) -> Result<String, DTOErrors> {
+    return sign_access_token(config, |my_claims| {
+        my_claims.sub = client_id.clone();
+        my_claims.client_id = Some(client_id);
+        my_claims.scope = Some(scope);
+    });
+}
+
+/// ID token of `user`, whose `sub` is `users.subject`, for `audience`, valid for `ACCESS_TOKEN_EXPIRY` minutes.
+pub(crate) fn id_token(config: &crate::Config, user: UserInfo, audience: &str, nonce: Option<String>, auth_time: i64) -> Result<String, DTOErrors> {
+    let dt = Utc::now();
+    let user = UserInfo { sub: config.subject_type.subject(&user.sub, audience), ..user };
+    let id_claims = IdTokenClaims {
+        iss: config.issuer_url.to_string(),
+        aud: audience.to_string(),
+        exp: dt.timestamp() + 60 * i64::from(config.access_token_exp),
+        iat: dt.timestamp(),
+        auth_time,
+        nonce,
+        user
+    };
+
+    return match encode(&config.keys.header(), &id_claims, config.keys.encoding_key()) {
+        Ok(token) => Ok(token),
+        Err(e) => Err(DTOErrors::ApplicationError(e.to_string()))
+    };
+}
+
+pub(crate) fn tokens(config: &crate::Config, access_token: String, refresh_token: Option<String>, id_token: Option<String>) -> Tokens {
+    return Tokens {
+        access_token,
+        refresh_token,
+        token_type: "Bearer".to_string(),
+        expires_in: 60 * i64::from(config.access_token_exp),
+        id_token
+    };
+}
+
+/// A username or email that is already taken (ER_DUP_ENTRY) is the caller's
+/// mistake rather than the database's.
+pub(crate) fn sign_up_error(e: my::Error) -> DTOErrors {
+    match e {
+        my::Error::MySqlError(ref e) if e.code == 1062 => DTOErrors::ApplicationError("Username or email is taken.".to_string()),
+        e => DTOErrors::DatabaseError(e.to_string())
+    }
+}
+
+/// Hash of a new password of a user, once it follows the password policy and isn't a recent one.
+pub(crate) fn new_password_hash(
+    config: &crate::Config,
+    db_conn: &my::Pool,
+    user_id: usize,
+    username: &str,
+    email: &str,
+    user_pass: &str
+) -> Result<String, DTOErrors> {
+    if let Err(e) = config.password_policy.check(user_pass, &[username, email]) {
+        return Err(DTOErrors::ValidationError(e));
+    }
+    password_history::check(config, db_conn, user_id, user_pass)?;
+    return password::hash(&config.password_hashing, user_pass);
+}
+
 fn send_email(config: &crate::Config, to: &String, username: &String, message: &str) {
     let subject = format!("Hi, {}. {}", username, message);
     let subject_html = format!("<h2>Hi, {}.</h2>", username);
@@ -154,3 +440,85 @@ fn send_email(config: &crate::Config, to: &String, username: &String, message: &
     }
 
 }
+
+/// Claims for tests, with struct update syntax for what a test cares about.
+#[cfg(test)]
+pub(crate) fn claims() -> Claims {
+    Claims {
+        iss: "localhost".to_string(),
+        aud: "AutoChat".to_string(),
+        sub: "authentication".to_string(),
+        exp: 1000,
+        iat: 100,
+        jti: "abc".to_string(),
+        username: None,
+        email: None,
+        client_id: None,
+        scope: None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn user_principal() {
+        let claims = Claims { username: Some("farhan".to_string()), email: Some("farhan@example.com".to_string()), ..claims() };
+
+        assert_eq!(claims.principal(), Some(Principal::User {
+            username: "farhan".to_string(),
+            email: "farhan@example.com".to_string()
+        }));
+        assert_eq!(claims.username(), Ok("farhan"));
+    }
+
+    #[test]
+    fn client_principal() {
+        let claims = Claims { client_id: Some("worker".to_string()), scope: Some("mail:send".to_string()), ..claims() };
+
+        assert_eq!(claims.principal(), Some(Principal::Client {
+            client_id: "worker".to_string(),
+            scope: "mail:send".to_string()
+        }));
+        assert!(claims.username().is_err());
+    }
+
+    #[test]
+    fn no_principal() {
+        let claims = Claims { username: Some("farhan".to_string()), client_id: Some("worker".to_string()), ..claims() };
+
+        assert_eq!(claims.principal(), None);
+    }
+
+    #[test]
+    fn public_subject() {
+        assert_eq!(SubjectType::Public.subject("k3Jd9", "AutoChat"), "k3Jd9");
+    }
+
+    #[test]
+    fn pairwise_subject() {
+        let pairwise = SubjectType::Pairwise { salt: "pepper".to_string() };
+
+        assert_eq!(pairwise.subject("k3Jd9", "AutoChat"), pairwise.subject("k3Jd9", "AutoChat"));
+        assert_ne!(pairwise.subject("k3Jd9", "AutoChat"), pairwise.subject("k3Jd9", "mailer"));
+        assert_ne!(pairwise.subject("k3Jd9", "AutoChat"), "k3Jd9");
+        assert_eq!(pairwise.subject("k3Jd9", "AutoChat").len(), 43);
+    }
+
+    #[test]
+    fn taken_username() {
+        let e = my::Error::MySqlError(my::MySqlError {
+            state: "23000".to_string(),
+            message: "Duplicate entry 'farhan' for key 'username'".to_string(),
+            code: 1062
+        });
+
+        assert_eq!(sign_up_error(e), DTOErrors::ApplicationError("Username or email is taken.".to_string()));
+    }
+
+    #[test]
+    fn token_hash() {
+        assert_eq!(hash_token("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+}
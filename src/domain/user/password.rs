@@ -0,0 +1,98 @@
+//! Password hashing.
+//!
+//! Passwords are hashed with Argon2id and stored as PHC strings, e.g.
+//! `$argon2id$v=19$m=19456,t=2,p=1$<salt>$<hash>`, which carry their own salt
+//! and parameters. Users created before that have a bcrypt hash of the password
+//! followed by the 4 character `users.salt`. Both verify, and `needs_rehash`
+//! tells when a hash should be replaced after the user signed in, i.e. when it
+//! is a bcrypt one or was made with other `ARGON2_*` parameters.
+use argon2::{self, ThreadMode, Variant, Version};
+use rand::Rng;
+use rand::thread_rng;
+use crate::domain::user::DTOErrors;
+
+/// `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`, the
+/// defaults are OWASP's minimum for Argon2id.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HashParams {
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32
+}
+
+impl Default for HashParams {
+    fn default() -> HashParams {
+        HashParams { memory_kib: 19456, iterations: 2, parallelism: 1 }
+    }
+}
+
+impl HashParams {
+    fn prefix(&self) -> String {
+        format!("$argon2id$v=19$m={},t={},p={}$", self.memory_kib, self.iterations, self.parallelism)
+    }
+}
+
+pub fn hash(params: &HashParams, password: &str) -> Result<String, DTOErrors> {
+    let salt: [u8; 16] = thread_rng().gen();
+    let config = argon2::Config {
+        variant: Variant::Argon2id,
+        version: Version::Version13,
+        mem_cost: params.memory_kib,
+        time_cost: params.iterations,
+        lanes: params.parallelism,
+        thread_mode: ThreadMode::Sequential,
+        secret: &[],
+        ad: &[],
+        hash_length: 32
+    };
+    return argon2::hash_encoded(password.as_bytes(), &salt, &config)
+        .map_err(|e| DTOErrors::ApplicationError(e.to_string()));
+}
+
+/// Whether `password` matches `hashed`, `salt` is only used by bcrypt hashes.
+pub fn verify(password: &str, hashed: &str, salt: Option<&str>) -> bool {
+    if hashed.starts_with("$argon2") {
+        return argon2::verify_encoded(hashed, password.as_bytes()).unwrap_or(false);
+    }
+    return bcrypt::verify(password.to_owned() + salt.unwrap_or(""), hashed).unwrap_or(false);
+}
+
+pub fn needs_rehash(params: &HashParams, hashed: &str) -> bool {
+    return !hashed.starts_with(&params.prefix());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params() -> HashParams {
+        HashParams { memory_kib: 64, iterations: 1, parallelism: 1 }
+    }
+
+    #[test]
+    fn argon2id() {
+        let hashed = hash(&params(), "correct horse").unwrap();
+
+        assert!(hashed.starts_with("$argon2id$v=19$m=64,t=1,p=1$"));
+        assert!(verify("correct horse", &hashed, None));
+        assert!(!verify("battery staple", &hashed, None));
+        assert!(!needs_rehash(&params(), &hashed));
+        assert!(needs_rehash(&HashParams::default(), &hashed));
+    }
+
+    #[test]
+    fn legacy_bcrypt() {
+        let hashed = bcrypt::hash("correct horseAb1x", 4).unwrap();
+
+        assert!(verify("correct horse", &hashed, Some("Ab1x")));
+        assert!(!verify("correct horse", &hashed, Some("Zz9y")));
+        assert!(!verify("battery staple", &hashed, Some("Ab1x")));
+        assert!(needs_rehash(&params(), &hashed));
+    }
+
+    #[test]
+    fn malformed_hash() {
+        assert!(!verify("correct horse", "$argon2id$nonsense", None));
+        assert!(!verify("correct horse", "", None));
+    }
+}
@@ -0,0 +1,108 @@
+//! The last `PASSWORD_HISTORY` passwords of every user, so that a new password
+//! can't be one of them. `record` keeps the table at that size, 0 turns the
+//! history off and empties it as users update their password. Keeping and
+//! pruning the history is left to the queries of `record`.
+use std::borrow::Cow;
+use mysql as my;
+use validator::{ValidationError, ValidationErrors};
+use crate::domain::user::{password, DTOErrors};
+
+fn reused() -> DTOErrors {
+    let mut error = ValidationError::new("reused");
+    error.message = Some(Cow::Borrowed("You have used this password recently, choose another one."));
+    let mut errors = ValidationErrors::new();
+    errors.add("password", error);
+    return DTOErrors::ValidationError(errors);
+}
+
+/// Whether `user_pass` matches one of `hashes`, bcrypt ones come with their salt.
+fn used(user_pass: &str, hashes: &[(String, Option<String>)]) -> bool {
+    return hashes.iter().any(|(hashed, salt)| password::verify(user_pass, hashed, salt.as_ref().map(|salt| salt.as_str())));
+}
+
+/// Fails with the `reused` validation code when `user_pass` is the user's
+/// current password or one of their last `PASSWORD_HISTORY`.
+pub fn check(config: &crate::Config, db_conn: &my::Pool, user_id: usize, user_pass: &str) -> Result<(), DTOErrors> {
+    if config.password_history == 0 {
+        return Ok(());
+    }
+
+    // The current password isn't in the history of users who haven't updated it since the history was added.
+    let hashes: Vec<(String, Option<String>)> = db_conn.prep_exec(r"
+        SELECT password, salt FROM users WHERE id = :user_id AND password IS NOT NULL
+        UNION ALL
+        (SELECT password, salt FROM password_history WHERE user_id = :user_id ORDER BY id DESC LIMIT :history)", params!{
+            "user_id" => &user_id,
+            "history" => &config.password_history
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (password, salt) = my::from_row(row);
+                (password, salt)
+            }).collect()
+        }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+    if used(user_pass, &hashes) {
+        return Err(reused());
+    }
+    return Ok(());
+}
+
+/// Adds the new password hash of a user and forgets the ones past `PASSWORD_HISTORY`.
+/// Call it before `users.password` is updated, so that the password being
+/// replaced is kept too when the user has no history yet.
+pub fn record(config: &crate::Config, transaction: &mut my::Transaction, user_id: usize, hashed: &str) -> Result<(), DTOErrors> {
+    if config.password_history > 0 {
+        transaction.prep_exec(r"
+            INSERT INTO password_history (user_id, password, salt)
+            SELECT id, password, salt FROM users
+            WHERE id = :user_id AND password IS NOT NULL
+                AND NOT EXISTS (SELECT 1 FROM password_history WHERE user_id = :user_id)", params!{
+                "user_id" => &user_id
+            }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+        transaction.prep_exec(r"INSERT INTO password_history (user_id, password) VALUES (:user_id, :password)", params!{
+            "user_id" => &user_id,
+            "password" => hashed
+        }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+    }
+
+    // MySQL doesn't take LIMIT in an IN subquery, only in a derived table.
+    transaction.prep_exec(r"
+        DELETE FROM password_history WHERE user_id = :user_id AND id NOT IN (
+            SELECT id FROM (
+                SELECT id FROM password_history WHERE user_id = :user_id ORDER BY id DESC LIMIT :history
+            ) kept
+        )", params!{
+            "user_id" => &user_id,
+            "history" => &config.password_history
+        }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reused_error() {
+        match reused() {
+            DTOErrors::ValidationError(e) => assert_eq!(e.field_errors()["password"][0].code, "reused"),
+            e => panic!("Expected a validation error, got {:?}", e)
+        }
+    }
+
+    #[test]
+    fn current_password() {
+        let params = password::HashParams { memory_kib: 64, iterations: 1, parallelism: 1 };
+        let hashes = vec![
+            (bcrypt::hash("correct horseAb1x", 4).unwrap(), Some("Ab1x".to_string())),
+            (password::hash(&params, "battery staple").unwrap(), None)
+        ];
+
+        assert!(used("correct horse", &hashes));
+        assert!(used("battery staple", &hashes));
+        assert!(!used("tr0ub4dor&3", &hashes));
+    }
+}
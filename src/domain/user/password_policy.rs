@@ -0,0 +1,232 @@
+//! Rules new passwords have to follow, `PASSWORD_*` in `.env`.
+//!
+//! Violations are reported like any other validation error of the `password`
+//! field, each with its own code: `length`, `lowercase`, `uppercase`, `digit`,
+//! `symbol`, `contains_identity`, `too_weak` and `breached`. Strength is zxcvbn's 0 to 4
+//! score, https://github.com/dropbox/zxcvbn, taking the username and email into
+//! account so that `farhan2019` isn't strong for farhan.
+use std::borrow::Cow;
+use validator::{ValidationError, ValidationErrors};
+use crate::domain::user::breached::BreachList;
+
+#[derive(Debug)]
+pub struct PasswordPolicy {
+    pub min_length: usize,
+    /// Password DTOs take at most 1024 characters whatever this is.
+    pub max_length: usize,
+    pub require_lowercase: bool,
+    pub require_uppercase: bool,
+    pub require_digit: bool,
+    pub require_symbol: bool,
+    /// Lowest zxcvbn score accepted, 0 accepts anything.
+    pub min_score: u8,
+    /// `BREACHED_PASSWORDS`, passwords in it are rejected whatever their score.
+    pub breached: BreachList
+}
+
+impl Default for PasswordPolicy {
+    fn default() -> PasswordPolicy {
+        PasswordPolicy {
+            min_length: 8,
+            max_length: 128,
+            require_lowercase: false,
+            require_uppercase: false,
+            require_digit: false,
+            require_symbol: false,
+            min_score: 2,
+            breached: BreachList::None
+        }
+    }
+}
+
+/// zxcvbn's verdict on a password.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Strength {
+    pub score: u8,
+    pub warning: Option<String>,
+    pub suggestions: Vec<String>
+}
+
+/// Strength of `password` for a user with `user_inputs`, i.e. their username and email.
+pub fn strength(password: &str, user_inputs: &[&str]) -> Strength {
+    match zxcvbn::zxcvbn(password, user_inputs) {
+        Ok(entropy) => {
+            let feedback = entropy.feedback().as_ref();
+            Strength {
+                score: entropy.score(),
+                warning: feedback.and_then(|feedback| feedback.warning()).map(|warning| warning.to_string()),
+                suggestions: feedback.map_or(vec![], |feedback| {
+                    feedback.suggestions().iter().map(|suggestion| suggestion.to_string()).collect()
+                })
+            }
+        },
+        // Only fails for empty passwords.
+        Err(_) => Strength { score: 0, warning: None, suggestions: vec![] }
+    }
+}
+
+fn error(code: &'static str, message: &str) -> ValidationError {
+    let mut error = ValidationError::new(code);
+    error.message = Some(Cow::Owned(message.to_string()));
+    error
+}
+
+/// The username, email and the local part of the email, lowercased.
+fn identities(user_inputs: &[&str]) -> Vec<String> {
+    let mut identities: Vec<String> = vec![];
+    for input in user_inputs {
+        let input = input.to_lowercase();
+        if let Some(local) = input.split('@').next().filter(|local| local.len() != input.len()) {
+            identities.push(local.to_string());
+        }
+        identities.push(input);
+    }
+    // Too short to tell apart from chance.
+    identities.into_iter().filter(|identity| identity.chars().count() >= 3).collect()
+}
+
+impl PasswordPolicy {
+    /// Strength of `password` and every rule it breaks, `user_inputs` are the
+    /// username and email of its user.
+    pub fn evaluate(&self, password: &str, user_inputs: &[&str]) -> (Strength, ValidationErrors) {
+        let mut errors = ValidationErrors::new();
+        let length = password.chars().count();
+
+        if length < self.min_length || length > self.max_length {
+            let mut e = error("length", &format!("Use {} to {} characters.", self.min_length, self.max_length));
+            e.add_param(Cow::Borrowed("min"), &self.min_length);
+            e.add_param(Cow::Borrowed("max"), &self.max_length);
+            errors.add("password", e);
+        }
+        if self.require_lowercase && !password.chars().any(char::is_lowercase) {
+            errors.add("password", error("lowercase", "Add a lowercase letter."));
+        }
+        if self.require_uppercase && !password.chars().any(char::is_uppercase) {
+            errors.add("password", error("uppercase", "Add an uppercase letter."));
+        }
+        if self.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+            errors.add("password", error("digit", "Add a digit."));
+        }
+        if self.require_symbol && !password.chars().any(|c| !c.is_alphanumeric()) {
+            errors.add("password", error("symbol", "Add a symbol."));
+        }
+
+        let lowercase = password.to_lowercase();
+        if identities(user_inputs).iter().any(|identity| lowercase.contains(identity.as_str())) {
+            errors.add("password", error("contains_identity", "Don't use your username or email in your password."));
+        }
+
+        // zxcvbn slows down with the length, passwords that are too long are rejected already.
+        let strength = if length > self.max_length {
+            Strength { score: 0, warning: None, suggestions: vec![] }
+        } else {
+            strength(password, user_inputs)
+        };
+        if strength.score < self.min_score && length <= self.max_length {
+            let mut e = error("too_weak", strength.warning.as_ref().map_or("Use a stronger password.", |warning| warning.as_str()));
+            e.add_param(Cow::Borrowed("score"), &strength.score);
+            e.add_param(Cow::Borrowed("min_score"), &self.min_score);
+            e.add_param(Cow::Borrowed("suggestions"), &strength.suggestions);
+            errors.add("password", e);
+        }
+
+        if self.breached.contains(password) {
+            errors.add("password", error("breached", "This password has appeared in a data breach, choose another one."));
+        }
+
+        return (strength, errors);
+    }
+
+    pub fn check(&self, password: &str, user_inputs: &[&str]) -> Result<(), ValidationErrors> {
+        let (_, errors) = self.evaluate(password, user_inputs);
+        if errors.is_empty() {
+            return Ok(());
+        }
+        return Err(errors);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(result: Result<(), ValidationErrors>) -> Vec<String> {
+        match result {
+            Ok(_) => vec![],
+            Err(e) => e.field_errors()["password"].iter().map(|e| e.code.to_string()).collect()
+        }
+    }
+
+    #[test]
+    fn strong_password() {
+        let (strength, errors) = PasswordPolicy::default().evaluate("correct horse battery staple", &["farhan", "farhan@example.com"]);
+
+        assert!(strength.score >= 3);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn length() {
+        let policy = PasswordPolicy { min_score: 0, ..Default::default() };
+
+        assert_eq!(codes(policy.check("Kx9#q", &[])), vec!["length"]);
+        assert_eq!(codes(policy.check(&"Kx9#q".repeat(30), &[])), vec!["length"]);
+    }
+
+    #[test]
+    fn too_long() {
+        let (strength, errors) = PasswordPolicy::default().evaluate(&"a".repeat(129), &[]);
+
+        assert_eq!(strength.score, 0);
+        assert_eq!(errors.field_errors()["password"].iter().map(|e| e.code.to_string()).collect::<Vec<String>>(), vec!["length"]);
+    }
+
+    #[test]
+    fn character_classes() {
+        let policy = PasswordPolicy {
+            require_lowercase: true,
+            require_uppercase: true,
+            require_digit: true,
+            require_symbol: true,
+            min_score: 0,
+            ..Default::default()
+        };
+
+        assert_eq!(codes(policy.check("ABCDEFGHIJ", &[])), vec!["lowercase", "digit", "symbol"]);
+        assert_eq!(codes(policy.check("abcdefghij", &[])), vec!["uppercase", "digit", "symbol"]);
+        assert_eq!(codes(policy.check("Abcdefgh1!", &[])), Vec::<String>::new());
+    }
+
+    #[test]
+    fn contains_identity() {
+        let policy = PasswordPolicy { min_score: 0, ..Default::default() };
+
+        assert_eq!(codes(policy.check("xFarhan-2019x", &["farhan", "someone@example.com"])), vec!["contains_identity"]);
+        assert_eq!(codes(policy.check("someone.rocks!", &["farhan", "someone@example.com"])), vec!["contains_identity"]);
+        assert_eq!(codes(policy.check("ab-ab-ab-ab", &["ab", "ab@example.com"])), Vec::<String>::new());
+    }
+
+    #[test]
+    fn too_weak() {
+        let result = PasswordPolicy::default().check("password1", &[]);
+
+        assert_eq!(codes(result), vec!["too_weak"]);
+    }
+
+    #[test]
+    fn breached() {
+        let breached = BreachList::Hashes(vec![[
+            0x5b, 0xaa, 0x61, 0xe4, 0xc9, 0xb9, 0x3f, 0x3f, 0x06, 0x82,
+            0x25, 0x0b, 0x6c, 0xf8, 0x33, 0x1b, 0x7e, 0xe6, 0x8f, 0xd8
+        ]].into_iter().collect());
+        let policy = PasswordPolicy { min_score: 0, breached, ..Default::default() };
+
+        assert_eq!(codes(policy.check("password", &[])), vec!["breached"]);
+        assert_eq!(codes(policy.check("correct horse battery staple", &[])), Vec::<String>::new());
+    }
+
+    #[test]
+    fn empty_password() {
+        assert_eq!(strength("", &[]).score, 0);
+    }
+}
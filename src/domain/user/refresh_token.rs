@@ -0,0 +1,130 @@
+use validator::{Validate};
+use mysql as my;
+use crate::domain::user::{access_token, hash_token, generate_random, tokens, RefreshTokenDTO, Tokens, UserInfo, DTOErrors};
+
+/// Stores a new refresh token of `family` for `user_id` and returns it.
+/// `audience` is the client it was issued to, or APP_NAME.
+pub fn issue(config: &crate::Config, transaction: &mut my::Transaction, user_id: usize, family: &str, audience: &str) -> Result<String, DTOErrors> {
+    let refresh_token = generate_random(64);
+
+    match transaction.prep_exec(r"
+        INSERT INTO refresh_tokens (user_id, family, token_hash, audience, expires_at)
+        VALUES (:user_id, :family, :token_hash, :audience, DATE_ADD(NOW(), INTERVAL :days DAY))", params!{
+            "user_id" => &user_id,
+            "family" => family,
+            "audience" => audience,
+            "token_hash" => hash_token(&refresh_token),
+            "days" => &config.token_exp
+        }) {
+        Ok(_) => return Ok(refresh_token),
+        Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+    };
+}
+
+/// What presenting a refresh token leads to.
+#[derive(Debug, PartialEq)]
+enum Rotation {
+    /// Unused, it's exchanged for a new one of the same family.
+    Rotate,
+    /// Its family was revoked, by a reuse or by signing out.
+    Reject,
+    /// Already exchanged once, so it or its successor leaked.
+    RevokeFamily
+}
+
+fn rotation(used: bool, revoked: bool) -> Rotation {
+    match (used, revoked) {
+        (_, true) => Rotation::Reject,
+        (true, false) => Rotation::RevokeFamily,
+        (false, false) => Rotation::Rotate
+    }
+}
+
+/// Exchanges a refresh token for a new access token and refresh token. A
+/// refresh token can only be used once, using it again revokes every token
+/// rotated from the same sign in.
+pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &RefreshTokenDTO) -> Result<Tokens, DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false)).unwrap();
+
+            let result: Vec<(usize, String, bool, bool, Option<String>, usize, String, String, String, bool)> = transaction.prep_exec(r"
+                SELECT rt.id, rt.family, rt.used, rt.revoked, rt.audience, u.id, u.subject, username, email, email_verified
+                FROM refresh_tokens rt
+                INNER JOIN users u ON u.id = rt.user_id
+                WHERE rt.token_hash = :token_hash AND rt.expires_at > NOW() AND u.enabled = 1
+                FOR UPDATE", params!{
+                    "token_hash" => hash_token(&data.refresh_token)
+                }).map(|result| {
+                    result.map(|x| x.unwrap()).map(|row| {
+                        // ⚠️ Note that from_row will panic if you don't follow your schema
+                        let (id, family, used, revoked, audience, user_id, subject, username, email, email_verified) = my::from_row(row);
+                        (id, family, used, revoked, audience, user_id, subject, username, email, email_verified)
+                    }).collect()
+                }).unwrap();
+
+            if result.is_empty() {
+                return Err(DTOErrors::ApplicationError("Incorrect refresh token.".to_string()));
+            }
+
+            let (id, family, used, revoked, audience, user_id, subject, username, email, email_verified) = result[0].clone();
+
+            match rotation(used, revoked) {
+                Rotation::Reject => return Err(DTOErrors::ApplicationError("Incorrect refresh token.".to_string())),
+                Rotation::RevokeFamily => {
+                    transaction.prep_exec(r"UPDATE refresh_tokens SET revoked = 1 WHERE family = :family", params!{
+                        "family" => &family
+                    }).map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+                    return match transaction.commit() {
+                        Ok(_) => Err(DTOErrors::ApplicationError("Refresh token reused, please sign in again.".to_string())),
+                        Err(e) => Err(DTOErrors::DatabaseError(e.to_string()))
+                    };
+                },
+                Rotation::Rotate => {}
+            };
+
+            transaction.prep_exec(r"UPDATE refresh_tokens SET used = 1 WHERE id = :id", params!{
+                "id" => &id
+            }).unwrap();
+
+            // Tokens issued before `audience` was recorded were all for APP_NAME.
+            let audience = audience.unwrap_or_else(|| config.app_name.to_string());
+            let user = UserInfo { sub: subject, preferred_username: username, email, email_verified };
+            let refresh_token = issue(config, &mut transaction, user_id, &family, &audience)?;
+            let access_token = access_token(config, &user, &audience)?;
+
+            match transaction.commit() {
+                Ok(_) => return Ok(tokens(config, access_token, Some(refresh_token), None)),
+                Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+            }
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_dto() {
+        assert!(RefreshTokenDTO { refresh_token: "".to_string() }.validate().is_err());
+    }
+
+    #[test]
+    fn unused_refresh_token() {
+        assert_eq!(rotation(false, false), Rotation::Rotate);
+    }
+
+    #[test]
+    fn reused_refresh_token() {
+        assert_eq!(rotation(true, false), Rotation::RevokeFamily);
+    }
+
+    #[test]
+    fn revoked_refresh_token() {
+        assert_eq!(rotation(false, true), Rotation::Reject);
+        assert_eq!(rotation(true, true), Rotation::Reject);
+    }
+}
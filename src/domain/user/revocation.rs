@@ -0,0 +1,141 @@
+//! Access tokens revoked before their `exp`.
+//!
+//! `app.sign_out` revokes a single token by its `jti`, `app.sign_out_everywhere`
+//! revokes every token of a user issued up to a point in time. Both are stored
+//! in the database so every process sees them, and cached in-process so
+//! `authenticate::run` doesn't hit the database for every token. The cache is
+//! reloaded once it is older than `REVOCATION_CACHE_TTL` seconds, revocations
+//! made by this process are cached right away. The cache lives in `AppState`.
+use mysql as my;
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use crate::domain::user::{Claims, DTOErrors};
+
+#[derive(Default)]
+struct Cache {
+    jtis: HashSet<String>,
+    revoked_before: HashMap<String, i64>,
+    refreshed_at: Option<Instant>
+}
+
+impl Cache {
+    fn contains(&self, claims: &Claims) -> bool {
+        if self.jtis.contains(&claims.jti) {
+            return true;
+        }
+        match claims.username.as_ref().and_then(|username| self.revoked_before.get(username)) {
+            Some(revoked_before) => claims.iat <= *revoked_before,
+            None => false
+        }
+    }
+
+    fn is_stale(&self, ttl: Duration) -> bool {
+        match self.refreshed_at {
+            Some(refreshed_at) => refreshed_at.elapsed() > ttl,
+            None => true
+        }
+    }
+}
+
+pub struct Revocations {
+    ttl: Duration,
+    cache: RwLock<Cache>
+}
+
+impl Revocations {
+    pub fn new(ttl: Duration) -> Revocations {
+        Revocations { ttl, cache: RwLock::new(Cache::default()) }
+    }
+
+    fn refresh(&self, config: &crate::Config, db_conn: &my::Pool) -> Result<(), DTOErrors> {
+        let jtis: HashSet<String> = db_conn.prep_exec(r"
+            SELECT jti FROM revoked_tokens WHERE expires_at > NOW()", ())
+            .map(|result| {
+                result.map(|x| x.unwrap()).map(|row| my::from_row(row)).collect()
+            })
+            .map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+        // Older cutoffs can't revoke a token that hasn't expired yet.
+        let revoked_before: HashMap<String, i64> = db_conn.prep_exec(r"
+            SELECT username, tokens_revoked_before FROM users
+            WHERE tokens_revoked_before > UNIX_TIMESTAMP() - :lifetime", params!{
+                "lifetime" => 60 * i64::from(config.access_token_exp)
+            })
+            .map(|result| {
+                result.map(|x| x.unwrap()).map(|row| {
+                    // ⚠️ Note that from_row will panic if you don't follow your schema
+                    let (username, revoked_before) = my::from_row(row);
+                    (username, revoked_before)
+                }).collect()
+            })
+            .map_err(|e| DTOErrors::DatabaseError(e.to_string()))?;
+
+        let mut cache = self.cache.write().unwrap();
+        *cache = Cache { jtis, revoked_before, refreshed_at: Some(Instant::now()) };
+        return Ok(());
+    }
+
+    pub fn is_revoked(&self, config: &crate::Config, db_conn: &my::Pool, claims: &Claims) -> Result<bool, DTOErrors> {
+        if self.cache.read().unwrap().is_stale(self.ttl) {
+            self.refresh(config, db_conn)?;
+        }
+        return Ok(self.cached(claims));
+    }
+
+    /// Whether `claims` are revoked as far as this process knows, without refreshing.
+    pub(crate) fn cached(&self, claims: &Claims) -> bool {
+        return self.cache.read().unwrap().contains(claims);
+    }
+
+    pub fn revoke(&self, jti: &str) {
+        self.cache.write().unwrap().jtis.insert(jti.to_string());
+    }
+
+    pub fn revoke_before(&self, username: &str, revoked_before: i64) {
+        self.cache.write().unwrap().revoked_before.insert(username.to_string(), revoked_before);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn claims(jti: &str, iat: i64) -> Claims {
+        Claims {
+            jti: jti.to_string(),
+            iat,
+            exp: iat + 900,
+            username: Some("farhan".to_string()),
+            email: Some("farhan@example.com".to_string()),
+            ..crate::domain::user::claims()
+        }
+    }
+
+    #[test]
+    fn revoked_jti() {
+        let mut cache = Cache::default();
+        cache.jtis.insert("abc".to_string());
+
+        assert!(cache.contains(&claims("abc", 100)));
+        assert!(!cache.contains(&claims("def", 100)));
+    }
+
+    #[test]
+    fn revoked_before() {
+        let mut cache = Cache::default();
+        cache.revoked_before.insert("farhan".to_string(), 100);
+
+        assert!(cache.contains(&claims("abc", 99)));
+        assert!(cache.contains(&claims("abc", 100)));
+        assert!(!cache.contains(&claims("abc", 101)));
+    }
+
+    #[test]
+    fn stale() {
+        assert!(Cache::default().is_stale(Duration::from_secs(30)));
+
+        let cache = Cache { refreshed_at: Some(Instant::now()), ..Default::default() };
+        assert!(!cache.is_stale(Duration::from_secs(30)));
+    }
+}
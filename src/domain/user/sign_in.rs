@@ -1,53 +1,85 @@
 use validator::{Validate};
 use mysql as my;
-use jwt::{encode, Header};
 use chrono::{Utc};
-use crate::domain::user::{verify, Claims, SignInDTO, DTOErrors};
+use crate::domain::user::{access_token, generate_random, id_token, password, refresh_token, tokens, SignInDTO, Tokens, UserInfo, DTOErrors};
 
-pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &SignInDTO) -> Result<String, DTOErrors> {
+/// Fails unless `user_pass` matches `hashed_pass`, `salt` is only used by bcrypt hashes.
+pub(crate) fn check_password(user_pass: &str, hashed_pass: &str, salt: Option<&str>) -> Result<(), DTOErrors> {
+    if !password::verify(user_pass, hashed_pass, salt) {
+        return Err(DTOErrors::ApplicationError("Incorrect password.".to_string()));
+    }
+    return Ok(());
+}
+
+/// Checks the username or email and password of `data`, returns the user's id and claims.
+/// Hashes that are bcrypt or use old Argon2 parameters are replaced once the password matches.
+pub fn credentials(config: &crate::Config, db_conn: &my::Pool, data: &SignInDTO) -> Result<(usize, UserInfo), DTOErrors> {
+    let result: Vec<(usize, String, String, String, bool, Option<String>, Option<String>)> = db_conn.prep_exec(r"
+        SELECT id, subject, username, email, email_verified, salt, password FROM `users` 
+        WHERE (username = :username OR email = :email) AND enabled = 1 LIMIT 1", params!{
+            "username" => &data.username_or_email.clone(),
+            "email" =>  &data.username_or_email.clone()
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (user_id, subject, username, email, email_verified, salt, password) = my::from_row(row);
+                (user_id, subject, username, email, email_verified, salt, password)
+            }).collect()
+        }).unwrap();
+
+    if result.is_empty() {
+        return Err(DTOErrors::ApplicationError("Incorrect username.".to_string()));
+    }
+
+    let (user_id, subject, username, email, email_verified, salt, hashed_pass) = result[0].clone();
+    // Users created by `app.sign_up_without_password` have no password until they set one.
+    let hashed_pass = hashed_pass.unwrap_or_default();
+
+    check_password(&data.password, &hashed_pass, salt.as_ref().map(|salt| salt.as_str()))?;
+
+    if password::needs_rehash(&config.password_hashing, &hashed_pass) {
+        rehash(config, db_conn, user_id, &data.password, &hashed_pass);
+    }
+
+    return Ok((user_id, UserInfo { sub: subject, preferred_username: username, email, email_verified }));
+}
+
+/// Best effort, the user is signed in either way and the hash is upgraded next time.
+fn rehash(config: &crate::Config, db_conn: &my::Pool, user_id: usize, user_pass: &str, hashed_pass: &str) {
+    let hashed = match password::hash(&config.password_hashing, user_pass) {
+        Ok(hashed) => hashed,
+        Err(e) => {
+            log::warn!("Unable to rehash the password of user {}: {:?}", user_id, e);
+            return;
+        }
+    };
+
+    // Unless the password changed in the meantime.
+    let result = db_conn.prep_exec(r"
+        UPDATE users SET password = :password, salt = NULL WHERE id = :user_id AND password = :hashed_pass", params!{
+            "password" => &hashed,
+            "user_id" => &user_id,
+            "hashed_pass" => hashed_pass
+        });
+
+    if let Err(e) = result {
+        log::warn!("Unable to rehash the password of user {}: {}", user_id, e);
+    }
+}
+
+pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &SignInDTO) -> Result<Tokens, DTOErrors> {
     match data.validate() {
         Ok(_) => {
-            let result: Vec<(String, String, String, String)> = db_conn.prep_exec(r"
-                SELECT username, email, salt, password FROM `users` 
-                WHERE (username = :username OR email = :email) AND enabled = 1 LIMIT 1", params!{
-                    "username" => &data.username_or_email.clone(),
-                    "email" =>  &data.username_or_email.clone()
-                }).map(|result| {
-                    result.map(|x| x.unwrap()).map(|row| {
-                        // ⚠️ Note that from_row will panic if you don't follow your schema
-                        let (username, email, salt, password) = my::from_row(row);
-                        (username, email, salt, password)
-                    }).collect()
-                }).unwrap();
-
-            if result.is_empty() {
-                return Err(DTOErrors::ApplicationError("Incorrect username.".to_string()));
-            }
+            let (user_id, user) = credentials(config, db_conn, data)?;
+
+            let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false)).unwrap();
+            let refresh_token = refresh_token::issue(config, &mut transaction, user_id, &generate_random(32), &config.app_name)?;
+            let access_token = access_token(config, &user, &config.app_name)?;
+            let id_token = id_token(config, user, &config.app_name, data.nonce.clone(), Utc::now().timestamp())?;
 
-            let (username, email, salt, hashed_pass) = result[0].clone();
-            let user_pass = data.password.to_owned() + &salt;
-            let verify_pass_result = verify(user_pass, &hashed_pass);
-
-            match verify_pass_result {
-                Ok(_) => {},
-                Err(_) => return Err(DTOErrors::ApplicationError("Incorrect password.".to_string()))
-            };
-
-            let dt = Utc::now();
-            let day_in_sec: i64 = (86400 * config.token_exp).into();
-            let my_claims = Claims {
-                iss: config.domain.to_string(),
-                aud: config.app_name.to_string(),
-                sub: config.subject.to_string(),
-                exp: dt.timestamp() + day_in_sec,
-                username,
-                email
-            };
-            let token_encoding = encode(&Header::default(), &my_claims, config.secret.as_ref());
-
-            return match token_encoding {
-                Ok(token) => Ok(token),
-                Err(e) => Err(DTOErrors::ApplicationError(e.to_string()))
+            match transaction.commit() {
+                Ok(_) => return Ok(tokens(config, access_token, Some(refresh_token), Some(id_token))),
+                Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
             }
         },
         Err(e) => return Err(DTOErrors::ValidationError(e))
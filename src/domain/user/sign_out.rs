@@ -0,0 +1,55 @@
+use validator::{Validate};
+use mysql as my;
+use crate::domain::user::revocation::Revocations;
+use crate::domain::user::{hash_token, Claims, SignOutDTO, DTOErrors};
+
+/// Revokes the token `claims` were read from and, when given, the refresh
+/// token it was issued with.
+pub fn run(db_conn: &my::Pool, revocations: &Revocations, claims: &Claims, data: &SignOutDTO) -> Result<bool, DTOErrors> {
+    match data.validate() {
+        Ok(_) => {
+            let username = claims.username()?;
+            let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false)).unwrap();
+
+            transaction.prep_exec(r"
+                INSERT IGNORE INTO revoked_tokens (jti, user_id, expires_at)
+                SELECT :jti, id, FROM_UNIXTIME(:exp) FROM users WHERE username = :username", params!{
+                    "jti" => &claims.jti,
+                    "exp" => &claims.exp,
+                    "username" => username
+                }).unwrap();
+
+            if let Some(ref refresh_token) = data.refresh_token {
+                transaction.prep_exec(r"
+                    UPDATE refresh_tokens rt
+                    INNER JOIN refresh_tokens signed_out ON signed_out.family = rt.family
+                    INNER JOIN users u ON u.id = signed_out.user_id
+                    SET rt.revoked = 1
+                    WHERE signed_out.token_hash = :token_hash AND u.username = :username", params!{
+                        "token_hash" => hash_token(refresh_token),
+                        "username" => username
+                    }).unwrap();
+            }
+
+            match transaction.commit() {
+                Ok(_) => {
+                    revocations.revoke(&claims.jti);
+                    return Ok(true)
+                },
+                Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+            }
+        },
+        Err(e) => return Err(DTOErrors::ValidationError(e))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_dto() {
+        assert!(SignOutDTO { refresh_token: Some("".to_string()) }.validate().is_err());
+        assert!(SignOutDTO { refresh_token: None }.validate().is_ok());
+    }
+}
@@ -0,0 +1,52 @@
+use mysql as my;
+use chrono::{Utc};
+use crate::domain::user::revocation::Revocations;
+use crate::domain::user::{Claims, DTOErrors};
+
+/// Revokes every token and refresh token of the user `claims` belong to.
+pub fn run(db_conn: &my::Pool, revocations: &Revocations, claims: &Claims) -> Result<bool, DTOErrors> {
+    // Tokens issued up to and including this second are revoked, `iat` has a one second resolution.
+    let username = claims.username()?;
+    let revoked_before = Utc::now().timestamp();
+
+    let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false)).unwrap();
+
+    transaction.prep_exec(r"
+        UPDATE users SET tokens_revoked_before = :revoked_before WHERE username = :username", params!{
+            "revoked_before" => &revoked_before,
+            "username" => username
+        }).unwrap();
+
+    transaction.prep_exec(r"
+        UPDATE refresh_tokens rt
+        INNER JOIN users u ON u.id = rt.user_id
+        SET rt.revoked = 1
+        WHERE u.username = :username", params!{
+            "username" => username
+        }).unwrap();
+
+    match transaction.commit() {
+        Ok(_) => {
+            revocations.revoke_before(username, revoked_before);
+            return Ok(true)
+        },
+        Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn revoked_up_to_cutoff() {
+        let revocations = Revocations::new(Duration::from_secs(30));
+        revocations.revoke_before("farhan", 100);
+        let claims = |iat| Claims { iat, username: Some("farhan".to_string()), ..crate::domain::user::claims() };
+
+        assert!(revocations.cached(&claims(100)));
+        assert!(!revocations.cached(&claims(101)));
+        assert!(!revocations.cached(&Claims { username: Some("someone".to_string()), ..claims(100) }));
+    }
+}
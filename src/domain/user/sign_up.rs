@@ -1,23 +1,24 @@
 use validator::{Validate};
 use mysql as my;
-use bcrypt::{hash};
-use crate::domain::user::{generate_random, send_email, SignUpDTO, DTOErrors};
+use crate::domain::user::{generate_random, password, send_email, sign_up_error, SignUpDTO, DTOErrors};
 
 pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &SignUpDTO) -> Result<bool, DTOErrors> {
     match data.validate() {
         Ok(_) => {
-            let salt: String = generate_random(4);
-            let user_pass = data.password.to_owned() + &salt;
-            let hashed = hash(user_pass, 4).expect("Unable to hash password.");
+            if let Err(e) = config.password_policy.check(&data.password, &[data.username.as_str(), data.email.as_str()]) {
+                return Err(DTOErrors::ValidationError(e));
+            }
+
+            let hashed = password::hash(&config.password_hashing, &data.password)?;
 
             let result = db_conn.prep_exec(r"INSERT INTO users
-                                    (username, email, password, salt)
+                                    (username, email, password, subject)
                                         VALUES
-                                    (:username, :email, :password, :salt)", params!{
+                                    (:username, :email, :password, :subject)", params!{
                     "username" => &data.username.clone(),
                     "email" =>  &data.email.clone(),
                     "password" => &hashed,
-                    "salt" => &salt
+                    "subject" => generate_random(32)
                 });
 
             match result {
@@ -28,7 +29,7 @@ pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &SignUpDTO) -> Resu
 
                     return Ok(true)
                 },
-                Err(e) => return Err(DTOErrors::DatabaseError(e.to_string()))
+                Err(e) => return Err(sign_up_error(e))
             }
         },
         Err(e) => return Err(DTOErrors::ValidationError(e))
@@ -1,19 +1,23 @@
 use validator::{Validate};
 use mysql as my;
-use crate::domain::user::{generate_random, send_email, SignUpWithoutPasswordDTO, DTOErrors};
+use crate::domain::user::{generate_random, send_email, sign_up_error, SignUpWithoutPasswordDTO, DTOErrors};
 
 pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &SignUpWithoutPasswordDTO) -> Result<bool, DTOErrors> {
     match data.validate() {
         Ok(_) => {
             let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false)).unwrap();
 
-            transaction.prep_exec(r"INSERT INTO users
-                                    (username, email)
+            let result = transaction.prep_exec(r"INSERT INTO users
+                                    (username, email, subject)
                                         VALUES
-                                    (:username, :email)", params!{
+                                    (:username, :email, :subject)", params!{
                     "username" => &data.username.clone(),
                     "email" =>  &data.email.clone(),
-                }).unwrap();
+                    "subject" => generate_random(32)
+                });
+            if let Err(e) = result {
+                return Err(sign_up_error(e));
+            }
 
             let user_id: Option<String> = transaction.first("SELECT LAST_INSERT_ID();").unwrap();
 
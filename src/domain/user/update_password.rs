@@ -1,7 +1,6 @@
 use validator::{Validate};
 use mysql as my;
-use bcrypt::{hash};
-use crate::domain::user::{generate_random, send_email, UpdatePasswordDTO, DTOErrors};
+use crate::domain::user::{new_password_hash, password_history, send_email, UpdatePasswordDTO, DTOErrors};
 
 pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &UpdatePasswordDTO) -> Result<bool, DTOErrors> {
     match data.validate() {
@@ -26,16 +25,15 @@ pub fn run(config: &crate::Config, db_conn: &my::Pool, data: &UpdatePasswordDTO)
 
             let (user_id, username, email) = result[0].clone();
 
+            let hashed = new_password_hash(config, db_conn, user_id, &username, &email, &data.password)?;
+
             let mut transaction = db_conn.start_transaction(true, Some(mysql::IsolationLevel::Serializable), Some(false)).unwrap();
 
-            let salt: String = generate_random(4);
-            let user_pass = data.password.to_owned() + &salt;
-            let hashed = hash(user_pass, 4).expect("Unable to hash password.");
+            password_history::record(config, &mut transaction, user_id, &hashed)?;
 
             transaction.prep_exec(r"
-                UPDATE users SET enabled = 1, password = :password, salt = :salt WHERE id = :user_id", params!{
+                UPDATE users SET enabled = 1, email_verified = 1, password = :password, salt = NULL WHERE id = :user_id", params!{
                     "password" => &hashed,
-                    "salt" =>  &salt,
                     "token" => &data.token,
                     "user_id" => &user_id
                 }).unwrap();
@@ -104,6 +102,10 @@ mod tests {
             std::borrow::Cow::Borrowed("min"),
             JsonValue::Number(Number::from(6)),
         );
+        error_value.insert(
+            std::borrow::Cow::Borrowed("max"),
+            JsonValue::Number(Number::from(1024)),
+        );
         validation_error.add("password", validator::ValidationError {
             code: std::borrow::Cow::Borrowed("length"),
             message: None,
@@ -0,0 +1,53 @@
+use mysql as my;
+use crate::domain::user::{Claims, UserInfo, DTOErrors};
+
+/// Id and standard claims of an enabled user, `sub` is `users.subject`.
+pub fn user(db_conn: &my::Pool, username: &str) -> Result<(usize, UserInfo), DTOErrors> {
+    let result: Vec<(usize, String, String, String, bool)> = db_conn.prep_exec(r"
+        SELECT id, subject, username, email, email_verified FROM `users`
+        WHERE username = :username AND enabled = 1 LIMIT 1", params!{
+            "username" => username
+        }).map(|result| {
+            result.map(|x| x.unwrap()).map(|row| {
+                // ⚠️ Note that from_row will panic if you don't follow your schema
+                let (user_id, subject, username, email, email_verified) = my::from_row(row);
+                (user_id, subject, username, email, email_verified)
+            }).collect()
+        }).unwrap();
+
+    if result.is_empty() {
+        return Err(DTOErrors::ApplicationError("User not found.".to_string()));
+    }
+
+    let (user_id, subject, username, email, email_verified) = result[0].clone();
+    return Ok((user_id, UserInfo { sub: subject, preferred_username: username, email, email_verified }));
+}
+
+/// Pairwise `sub`s differ per client, the token already carries the one its client sees.
+fn with_subject(userinfo: UserInfo, claims: &Claims) -> UserInfo {
+    return UserInfo { sub: claims.sub.clone(), ..userinfo };
+}
+
+/// Standard claims of the user `claims` belong to, with the same `sub` as the token.
+pub fn run(db_conn: &my::Pool, claims: &Claims) -> Result<UserInfo, DTOErrors> {
+    return user(db_conn, claims.username()?).map(|(_, userinfo)| with_subject(userinfo, claims));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_subject() {
+        let userinfo = UserInfo {
+            sub: "users.subject".to_string(),
+            preferred_username: "farhan".to_string(),
+            email: "farhan@example.com".to_string(),
+            email_verified: true
+        };
+        let userinfo = with_subject(userinfo, &crate::domain::user::claims());
+
+        assert_eq!(userinfo.sub, "authentication");
+        assert_eq!(userinfo.preferred_username, "farhan");
+    }
+}
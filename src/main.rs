@@ -6,6 +6,7 @@
 extern crate jsonwebtoken as jwt;
 extern crate chrono;
 extern crate env_logger;
+extern crate actix;
 
 mod database;
 mod api;
@@ -22,18 +23,41 @@ pub struct Config {
     create_password_url: String,
     app_name: String,
     domain: String,
-    secret: String,
-    subject: String,
+    issuer_url: String,
+    device_verification_url: String,
+    keys: domain::user::keys::KeyStore,
+    subject_type: domain::user::SubjectType,
     token_exp: i32,
+    access_token_exp: i32,
+    password_hashing: domain::user::password::HashParams,
+    password_policy: domain::user::password_policy::PasswordPolicy,
+    password_history: usize,
     sender_email: String,
     smtp_user: String,
     smtp_pass: String,
-    smtp_server: String
+    smtp_server: String,
+    rpc_legacy_errors: bool
 }
 
-struct AppState {
+pub struct AppState {
     config: Config,
-    db_conn: my::Pool
+    db_conn: my::Pool,
+    router: api::router::Router,
+    hub: api::ws::Hub,
+    revocations: domain::user::revocation::Revocations
+}
+
+impl AppState {
+    pub fn context<'a>(&'a self, claims: Option<&'a domain::user::Claims>) -> api::router::Context<'a> {
+        api::router::Context { config: &self.config, db_conn: &self.db_conn, hub: &self.hub, revocations: &self.revocations, claims }
+    }
+}
+
+fn router() -> api::router::Router {
+    let mut router = api::router::Router::new();
+    api::user::register(&mut router);
+    api::oauth::register(&mut router);
+    router
 }
 
 fn config() -> Config {
@@ -41,28 +65,82 @@ fn config() -> Config {
     let create_password_url = env::var("CREATE_PASSWORD_URL").expect("CREATE_PASSWORD_URL needs to be set.");
     let app_name = env::var("APP_NAME").expect("APP_NAME needs to be set.");
     let domain = env::var("DOMAIN").expect("DOMAIN needs to be set.");
-    let secret = env::var("SECRET").expect("SECRET needs to be set.");
-    let subject = env::var("SUBJECT").expect("SUBJECT needs to be set.");
+    let issuer_url = env::var("ISSUER_URL").unwrap_or(format!("http://{}", domain));
+    let device_verification_url = env::var("DEVICE_VERIFICATION_URL").unwrap_or(format!("{}/device", issuer_url));
+    let jwt_algorithm = env::var("JWT_ALGORITHM").unwrap_or("HS256".to_string());
+    let keys = if jwt_algorithm == "HS256" {
+        domain::user::keys::KeyStore::hs256(&env::var("SECRET").expect("SECRET needs to be set."))
+    } else {
+        domain::user::keys::KeyStore::load(
+            &jwt_algorithm,
+            &env::var("JWT_SIGNING_KEY").expect("JWT_SIGNING_KEY needs to be set."),
+            &env::var("JWT_SIGNING_KID").expect("JWT_SIGNING_KID needs to be set."),
+            &env::var("JWT_VERIFICATION_KEYS").expect("JWT_VERIFICATION_KEYS needs to be set.")
+        )
+    };
+    let subject_type = match env::var("SUBJECT_TYPE").unwrap_or("public".to_string()).as_str() {
+        "public" => domain::user::SubjectType::Public,
+        "pairwise" => domain::user::SubjectType::Pairwise {
+            salt: env::var("PAIRWISE_SALT").ok().filter(|salt| !salt.is_empty()).expect("PAIRWISE_SALT needs to be set and not empty.")
+        },
+        other => panic!("Unsupported SUBJECT_TYPE {}.", other)
+    };
     let token_exp = env::var("TOKEN_EXPIRY").expect("TOKEN_EXPIRY needs to be set.");
+    let access_token_exp = env::var("ACCESS_TOKEN_EXPIRY").unwrap_or("15".to_string());
+    let default_hashing = domain::user::password::HashParams::default();
+    let password_hashing = domain::user::password::HashParams {
+        memory_kib: env::var("ARGON2_MEMORY_KIB").map(|v| v.parse::<u32>().unwrap()).unwrap_or(default_hashing.memory_kib),
+        iterations: env::var("ARGON2_ITERATIONS").map(|v| v.parse::<u32>().unwrap()).unwrap_or(default_hashing.iterations),
+        parallelism: env::var("ARGON2_PARALLELISM").map(|v| v.parse::<u32>().unwrap()).unwrap_or(default_hashing.parallelism)
+    };
     let sender_email = env::var("SENDER_EMAIL").expect("SENDER_EMAIL needs to be set.");
     let smtp_user = env::var("SMTP_USER").expect("SMTP_USER needs to be set.");
     let smtp_pass = env::var("SMTP_PASS").expect("SMTP_PASS needs to be set.");
     let smtp_server = env::var("SMTP_USER").expect("SMTP_USER needs to be set.");
+    let rpc_legacy_errors = env::var("RPC_LEGACY_ERRORS").unwrap_or("false".to_string());
+    let default_policy = domain::user::password_policy::PasswordPolicy::default();
+    let password_classes = env::var("PASSWORD_REQUIRE").unwrap_or_default();
+    let password_classes: Vec<&str> = password_classes.split(',').map(|class| class.trim()).collect();
+    let password_policy = domain::user::password_policy::PasswordPolicy {
+        min_length: env::var("PASSWORD_MIN_LENGTH").map(|v| v.parse::<usize>().unwrap()).unwrap_or(default_policy.min_length),
+        max_length: env::var("PASSWORD_MAX_LENGTH").map(|v| v.parse::<usize>().unwrap()).unwrap_or(default_policy.max_length),
+        require_lowercase: password_classes.contains(&"lowercase"),
+        require_uppercase: password_classes.contains(&"uppercase"),
+        require_digit: password_classes.contains(&"digit"),
+        require_symbol: password_classes.contains(&"symbol"),
+        min_score: env::var("PASSWORD_MIN_SCORE").map(|v| v.parse::<u8>().unwrap()).unwrap_or(default_policy.min_score),
+        breached: env::var("BREACHED_PASSWORDS").ok().filter(|path| !path.is_empty()).map_or(default_policy.breached, |path| {
+            domain::user::breached::BreachList::load(&path).unwrap_or_else(|e| panic!("Unable to load {}: {}", path, e))
+        })
+    };
+    let password_history = env::var("PASSWORD_HISTORY").unwrap_or("5".to_string());
     Config {
         rust_env,
         create_password_url,
         app_name,
         domain,
-        secret,
-        subject,
+        issuer_url,
+        device_verification_url,
+        keys,
+        subject_type,
         token_exp: token_exp.parse::<i32>().unwrap(),
+        access_token_exp: access_token_exp.parse::<i32>().unwrap(),
+        password_hashing,
+        password_policy,
+        password_history: password_history.parse::<usize>().unwrap(),
         sender_email,
         smtp_user,
         smtp_pass,
-        smtp_server
+        smtp_server,
+        rpc_legacy_errors: rpc_legacy_errors == "true"
     }
 }
 
+fn revocations() -> domain::user::revocation::Revocations {
+    let revocation_cache_ttl = env::var("REVOCATION_CACHE_TTL").unwrap_or("30".to_string());
+    return domain::user::revocation::Revocations::new(std::time::Duration::from_secs(revocation_cache_ttl.parse::<u64>().unwrap()));
+}
+
 fn database_connection() -> my::Pool {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL needs to be set.");
     return my::Pool::new(&env::var("DATABASE_URL").expect("DATABASE_URL needs to be set.")).unwrap_or_else(|_| panic!("Error connecting to {}", database_url));
@@ -73,32 +151,39 @@ fn index(data: web::Data<AppState>) -> impl Responder {
     HttpResponse::Ok().body(format!("Hello {}!", app_name))
 }
 
-fn api(data: web::Data<AppState>, message: web::Json<api::user::RPCRequest>) -> Result<HttpResponse> {
-    if message.method == "app.sign_up" {
-        return api::user::sign_up(&data.config, &data.db_conn, &message);
-    } else if message.method == "app.sign_up_without_password" {
-        return api::user::sign_up_without_password(&data.config, &data.db_conn, &message);
-    } else if message.method == "app.sign_in" {
-        return api::user::sign_in(&data.config, &data.db_conn, &message);
-    } else if message.method == "app.authenticate" {
-        return api::user::authenticate(&data.config, &message);
-    }  else if message.method == "app.update_password" {
-        return api::user::update_password(&data.config, &data.db_conn, &message);
-    }  else if message.method == "app.identity_check" {
-        return api::user::identity_check(&data.db_conn, &message);
-    }  else if message.method == "app.forgot_my_password" {
-        return api::user::forgot_my_password(&data.config, &data.db_conn, &message);
-    } else {
-        Ok(HttpResponse::NotFound()
-            .json(json!({
-                "jsonrpc": message.jsonrpc.to_string(),
-                "result": json!({ "status": "error", "error": "Method doesn't exist." }),
-                "id": message.id.to_string()
-            })))
+fn api(data: web::Data<AppState>, bearer: Option<api::auth::Bearer>, body: String) -> Result<HttpResponse> {
+    let claims = bearer.as_ref().map(|bearer| &bearer.0);
+    match data.router.handle(&data.context(claims), &body) {
+        Some(response) => Ok(response.to_http()),
+        None => Ok(HttpResponse::NoContent().finish())
+    }
+}
+
+/// `auto-chat-api build-breach-filter <list> <filter> [false_positive_rate]`, see `domain::user::breached`.
+fn build_breach_filter(args: &[String]) {
+    if args.len() < 2 {
+        eprintln!("Usage: auto-chat-api build-breach-filter <pwned-passwords-sha1.txt> <output> [false_positive_rate]");
+        std::process::exit(2);
+    }
+    let false_positive_rate = args.get(2)
+        .map(|rate| rate.parse::<f64>().expect("false_positive_rate needs to be a number."))
+        .unwrap_or(domain::user::breached::DEFAULT_FALSE_POSITIVE_RATE);
+
+    match domain::user::breached::build_filter(&args[0], &args[1], false_positive_rate) {
+        Ok(items) => println!("Wrote {} passwords to {}.", items, args[1]),
+        Err(e) => {
+            eprintln!("Unable to build {}: {}", args[1], e);
+            std::process::exit(1);
+        }
     }
 }
 
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.first().map(|command| command.as_str()) == Some("build-breach-filter") {
+        return build_breach_filter(&args[1..]);
+    }
+
     dotenv().ok();
     let rust_env = env::var("RUST_ENV").unwrap_or("development".to_string());
     let port = env::var("PORT").unwrap_or("8000".to_string());
@@ -116,14 +201,24 @@ fn main() {
 
     let context = web::Data::new(AppState {
         config: config(),
-        db_conn: database_connection()
+        db_conn: database_connection(),
+        router: router(),
+        hub: api::ws::Hub::new(),
+        revocations: revocations()
     });
+    // The gRPC server stops when `_grpc_server` is dropped, i.e. when the HTTP server does.
+    let _grpc_server = env::var("GRPC_PORT").ok().filter(|grpc_port| !grpc_port.is_empty()).map(|grpc_port| {
+        api::grpc::serve(context.clone(), grpc_port.parse::<u16>().expect("GRPC_PORT needs to be a port number."))
+    });
+    for (name, meta) in context.router.methods() {
+        log::info!("Registered RPC method {} (auth required: {}, rate limit: {:?})", name, meta.auth_required, meta.rate_limit);
+    }
     HttpServer::new(move || {
         App::new()
             .wrap(
                 Cors::new()
                     .allowed_origin(&allowed_origin)
-                    .allowed_methods(vec!["GET", "POST"])
+                    .allowed_methods(vec!["GET", "POST", "PUT"])
                     .allowed_headers(vec![header::AUTHORIZATION, header::ACCEPT])
                     .allowed_header(header::CONTENT_TYPE)
                     .max_age(3600),
@@ -133,6 +228,23 @@ fn main() {
             // .data() <- create app with non-shared state
             .route("/", web::get().to(index))
             .route("/api", web::post().to(api))
+            .route("/users", web::post().to(api::rest::create_user))
+            .route("/sessions", web::post().to(api::rest::create_session))
+            .route("/sessions/refresh", web::post().to(api::rest::refresh_session))
+            .route("/password-resets", web::post().to(api::rest::create_password_reset))
+            .route("/password", web::put().to(api::rest::update_password))
+            .route("/identities/{id}", web::get().to(api::rest::get_identity))
+            .route("/openapi.json", web::get().to(api::rest::openapi))
+            .route("/.well-known/jwks.json", web::get().to(api::well_known::jwks))
+            .route("/.well-known/openid-configuration", web::get().to(api::well_known::openid_configuration))
+            .route("/authorize", web::get().to(api::oauth::authorize))
+            .route("/authorize", web::post().to(api::oauth::authorize_form))
+            .route("/token", web::post().to(api::oauth::token))
+            .route("/device_authorization", web::post().to(api::oauth::device_authorization))
+            .route("/introspect", web::post().to(api::oauth::introspect))
+            .route("/userinfo", web::get().to(api::oidc::userinfo))
+            .route("/userinfo", web::post().to(api::oidc::userinfo))
+            .route("/ws", web::get().to(api::ws::index))
     })
     .bind(format!("127.0.0.1:{}", port))
     .unwrap()